@@ -73,12 +73,13 @@ impl Object for User {
 impl Entity for User {
     const NAME: &'static str = "User";
 
+    type Key = ObjectId;
     type Services = Services;
     type Conditions = EmptyConditions;
     type Sorting = EmptySorting;
 
     fn id(&self) -> EntityId<Self> {
-        self.id
+        self.id.clone()
     }
 
     async fn before_save(
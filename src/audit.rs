@@ -0,0 +1,532 @@
+use super::*;
+
+use bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use bson::DateTime as BsonDateTime;
+
+use mongodb::error::Result as DatabaseResult;
+use mongodb::options::FindOptions;
+
+use std::collections::HashMap;
+
+const AUDIT_COLLECTION_NAME: &str = "auditEntry";
+
+fn audit_collection<S: EntityServices>(
+    ctx: &EntityContext<S>,
+) -> Collection<Document> {
+    ctx.database().collection(AUDIT_COLLECTION_NAME)
+}
+
+/// An entity whose changes are recorded in an audit log, as part of the
+/// transaction that makes them.
+///
+/// Auditing is enabled by returning an [`Auditor`] from
+/// [`Entity::auditor`]. Every write made through entrust is recorded,
+/// including bulk writes, atomic find-and-modify queries and the writes made
+/// to dependents.
+///
+/// The audit log is kept in the database of each entity's tenant (see
+/// [`EntityContext::with_tenant`]), so the history of an entity is only
+/// visible through a context of its tenant.
+pub trait Audited: Entity {
+    /// The changes recorded for the entity with `id`, oldest first.
+    fn history(id: EntityId<Self>) -> HistoryQuery<Self> {
+        HistoryQuery::new(id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditOperation {
+    Create,
+    Update,
+    Delete,
+    Discard,
+    Restore,
+}
+
+/// Records changes to entities of type `T` in the audit log.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""), Clone(bound = ""))]
+pub struct Auditor<T: Entity> {
+    discarded_at_path: Option<String>,
+    phantom: PhantomData<T>,
+}
+
+impl<T: Audited> Auditor<T> {
+    pub fn new() -> Self {
+        Self {
+            discarded_at_path: None,
+            phantom: default(),
+        }
+    }
+}
+
+impl<T: Audited> Default for Auditor<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Audited + Discardable> Auditor<T> {
+    /// Records saves that discard or restore the entity as such, rather than
+    /// as updates.
+    pub fn discardable(mut self) -> Self {
+        self.discarded_at_path = Some(T::discarded_at_path());
+        self
+    }
+}
+
+impl<T: Entity> Auditor<T> {
+    /// Loads the current states of the documents that match `conditions`, to
+    /// be recorded as the states prior to a bulk change.
+    pub(super) async fn load_priors(
+        &self,
+        collection: &Collection<Document>,
+        conditions: Document,
+        transaction: &mut Transaction,
+    ) -> Result<Vec<Document>> {
+        let Transaction { session, .. } = transaction;
+        let mut cursor = collection
+            .find_with_session(conditions, None, session)
+            .await
+            .context("failed to load prior states")?;
+        let mut priors = Vec::new();
+        while let Some(doc) = cursor.next(session).await {
+            let doc = doc.context("failed to load prior state")?;
+            priors.push(doc);
+        }
+        Ok(priors)
+    }
+
+    /// Records a bulk change to the documents whose prior states are
+    /// `priors`, loading their current states.
+    pub(super) async fn record_changes(
+        &self,
+        ctx: &EntityContext<T::Services>,
+        collection: &Collection<Document>,
+        transaction: &mut Transaction,
+        priors: &[Document],
+    ) -> Result<()> {
+        if priors.is_empty() {
+            return Ok(());
+        }
+        let ids = priors
+            .iter()
+            .map(|prior| prior.get("_id").cloned())
+            .collect::<Option<Vec<_>>>()
+            .context("missing document id")?;
+        let mut afters = {
+            let conditions = doc! { "_id": { "$in": ids } };
+            let afters = self
+                .load_priors(collection, conditions, transaction)
+                .await?;
+            afters
+                .into_iter()
+                .map(|doc| -> Result<_> {
+                    let id = doc.get("_id").context("missing document id")?;
+                    Ok((id.to_string(), doc))
+                })
+                .collect::<Result<HashMap<_, _>>>()?
+        };
+        for before in priors {
+            let id = before.get("_id").context("missing document id")?;
+            let after = afters.remove(&id.to_string());
+            let id = EntityId::from_bson(id)?;
+            let (before, after) = (Some(before), after.as_ref());
+            let operation = self.classify(before, after);
+            self.record(ctx, transaction, id, operation, before, after)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Serializes the states of `entities`, to be recorded as the states
+    /// prior to a bulk change.
+    pub(super) fn snapshot(
+        &self,
+        ctx: &EntityContext<T::Services>,
+        entities: &[T],
+    ) -> Result<Vec<Document>> {
+        ctx.with_encryption(|| {
+            entities
+                .iter()
+                .map(|entity| {
+                    entity.to_document().context("failed to serialize record")
+                })
+                .collect()
+        })
+    }
+
+    /// Records a bulk change to `entities`, whose prior states are `priors`.
+    pub(super) async fn record_all(
+        &self,
+        ctx: &EntityContext<T::Services>,
+        transaction: &mut Transaction,
+        entities: &[T],
+        priors: &[Document],
+        operation: AuditOperation,
+    ) -> Result<()> {
+        let afters = self.snapshot(ctx, entities)?;
+        let changes = entities.iter().zip(priors).zip(&afters);
+        for ((entity, before), after) in changes {
+            let id = entity.id();
+            let (before, after) = (Some(before), Some(after));
+            self.record(ctx, transaction, id, operation, before, after)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn classify(
+        &self,
+        before: Option<&Document>,
+        after: Option<&Document>,
+    ) -> AuditOperation {
+        let (before, after) = match (before, after) {
+            (None, _) => return AuditOperation::Create,
+            (_, None) => return AuditOperation::Delete,
+            (Some(before), Some(after)) => (before, after),
+        };
+        if let Some(path) = &self.discarded_at_path {
+            let was_discarded = is_set(before, path);
+            let is_discarded = is_set(after, path);
+            if !was_discarded && is_discarded {
+                return AuditOperation::Discard;
+            }
+            if was_discarded && !is_discarded {
+                return AuditOperation::Restore;
+            }
+        }
+        AuditOperation::Update
+    }
+
+    pub(super) async fn record(
+        &self,
+        ctx: &EntityContext<T::Services>,
+        transaction: &mut Transaction,
+        id: EntityId<T>,
+        operation: AuditOperation,
+        before: Option<&Document>,
+        after: Option<&Document>,
+    ) -> Result<()> {
+        let collection = audit_collection(ctx);
+        let entry_id = ObjectId::new();
+        let doc = doc! {
+            "_id": entry_id,
+            "entity": T::NAME,
+            "entityId": &id,
+            "operation": bson::to_bson(&operation)?,
+            "actor": ctx.actor(),
+            "changes": ctx.with_encryption(|| diff_documents(before, after)),
+            "createdAt": BsonDateTime::from_chrono(now()),
+        };
+
+        transaction.track_inserted(&collection, entry_id);
+        let Transaction { session, .. } = transaction;
+        trace!(
+            collection = collection.name(),
+            entity = T::NAME,
+            %id,
+            ?operation,
+            "recording audit entry"
+        );
+        collection
+            .insert_one_with_session(doc, None, session)
+            .await
+            .context("failed to record audit entry")?;
+        Ok(())
+    }
+}
+
+/// Whether the field at `path` (i.e. "a.b") is set to a non-null value.
+fn is_set(doc: &Document, path: &str) -> bool {
+    let mut doc = doc;
+    let mut segments = path.split('.').peekable();
+    while let Some(segment) = segments.next() {
+        match doc.get(segment) {
+            None | Some(Bson::Null) => return false,
+            Some(Bson::Document(nested)) if segments.peek().is_some() => {
+                doc = nested
+            }
+            Some(_) => return segments.peek().is_none(),
+        }
+    }
+    false
+}
+
+/// The top-level fields that differ between `before` and `after`, each with
+/// its `before` and `after` value (omitted where the field is absent).
+///
+/// Fields holding [`Encrypted`] values are compared by their plaintext (if
+/// the current key provider can decrypt them), and their values are left
+/// out of the change.
+fn diff_documents(
+    before: Option<&Document>,
+    after: Option<&Document>,
+) -> Document {
+    let empty = Document::new();
+    let before = before.unwrap_or(&empty);
+    let after = after.unwrap_or(&empty);
+
+    let added = after.keys().filter(|key| !before.contains_key(key));
+    let mut changes = Document::new();
+    for key in before.keys().chain(added) {
+        let (old, new) = (before.get(key), after.get(key));
+        if old == new {
+            continue;
+        }
+        let (old_plain, old_encrypted) = reveal(old);
+        let (new_plain, new_encrypted) = reveal(new);
+        if old_encrypted || new_encrypted {
+            if old_plain != new_plain {
+                changes.insert(key.to_owned(), doc! { "encrypted": true });
+            }
+            continue;
+        }
+        let mut change = Document::new();
+        if let Some(old) = old {
+            change.insert("before", old.to_owned());
+        }
+        if let Some(new) = new {
+            change.insert("after", new.to_owned());
+        }
+        changes.insert(key.to_owned(), change);
+    }
+    changes
+}
+
+/// Decrypts the [`Encrypted`] values within `value`, reporting whether
+/// there were any.
+fn reveal(value: Option<&Bson>) -> (Option<Bson>, bool) {
+    fn reveal_bson(value: &Bson, encrypted: &mut bool) -> Bson {
+        match value {
+            Bson::Binary(binary) => match reveal_value(binary) {
+                Some(value) => {
+                    *encrypted = true;
+                    value
+                }
+                None => value.to_owned(),
+            },
+            Bson::Document(doc) => {
+                let doc = doc
+                    .iter()
+                    .map(|(key, value)| {
+                        (key.to_owned(), reveal_bson(value, encrypted))
+                    })
+                    .collect();
+                Bson::Document(doc)
+            }
+            Bson::Array(values) => {
+                let values = values
+                    .iter()
+                    .map(|value| reveal_bson(value, encrypted))
+                    .collect();
+                Bson::Array(values)
+            }
+            value => value.to_owned(),
+        }
+    }
+
+    let mut encrypted = false;
+    let value = value.map(|value| reveal_bson(value, &mut encrypted));
+    (value, encrypted)
+}
+
+/// A change recorded in the audit log of an [`Audited`] entity.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""), Clone(bound = ""))]
+pub struct AuditEntry<T: Entity> {
+    pub id: ObjectId,
+    pub entity_id: EntityId<T>,
+    pub operation: AuditOperation,
+    pub actor: Option<String>,
+
+    /// The fields that changed, each mapped to a document with its `before`
+    /// and `after` value.
+    pub changes: Document,
+
+    pub created_at: DateTime,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuditDocument {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+
+    entity_id: Bson,
+    operation: AuditOperation,
+    actor: Option<String>,
+    changes: Document,
+
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    created_at: DateTime,
+}
+
+impl<T: Entity> TryFrom<AuditDocument> for AuditEntry<T> {
+    type Error = Error;
+
+    fn try_from(doc: AuditDocument) -> Result<Self> {
+        let AuditDocument {
+            id,
+            entity_id,
+            operation,
+            actor,
+            changes,
+            created_at,
+        } = doc;
+        let entry = Self {
+            id,
+            entity_id: EntityId::from_bson(&entity_id)?,
+            operation,
+            actor,
+            changes,
+            created_at,
+        };
+        Ok(entry)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HistoryQuery<T: Entity> {
+    entity_id: EntityId<T>,
+}
+
+impl<T: Entity> HistoryQuery<T> {
+    pub fn new(entity_id: EntityId<T>) -> Self {
+        Self { entity_id }
+    }
+
+    pub async fn load(
+        self,
+        ctx: &EntityContext<T::Services>,
+    ) -> Result<impl Stream<Item = Result<AuditEntry<T>>>> {
+        let Self { entity_id } = self;
+        let collection = audit_collection(ctx);
+        let conditions = doc! {
+            "entity": T::NAME,
+            "entityId": entity_id,
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "createdAt": 1, "_id": 1 })
+            .build();
+
+        let cursor: Box<
+            dyn Stream<Item = DatabaseResult<Document>> + Send + Unpin,
+        > = if let Some(handle) = ctx.session().await? {
+            let cursor = {
+                let mut session = handle.lock().await;
+                trace!(
+                    collection = collection.name(),
+                    %conditions,
+                    session = %session.id(),
+                    "finding audit entries"
+                );
+                collection
+                    .find_with_session(conditions, options, &mut session)
+                    .await?
+            };
+            Box::new(SessionStream::new(cursor, handle))
+        } else {
+            trace!(
+                collection = collection.name(),
+                %conditions,
+                "finding audit entries"
+            );
+            let cursor = collection.find(conditions, options).await?;
+            Box::new(cursor)
+        };
+
+        let stream = cursor.map(|doc| -> Result<_> {
+            let doc: AuditDocument = bson::from_document(doc?)
+                .context("failed to deserialize audit entry")?;
+            doc.try_into()
+        });
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_keys<R>(f: impl FnOnce() -> R) -> R {
+        let key = EncryptionKey::new("current", [1; 32]);
+        let provider: Arc<dyn KeyProvider> = Arc::new(EncryptionKeys::new(key));
+        with_key_provider(Some(provider), f)
+    }
+
+    fn encrypted(value: &str) -> Result<Bson> {
+        let value = Encrypted::<_>::new(value.to_owned());
+        let bson = bson::to_bson(&value)?;
+        Ok(bson)
+    }
+
+    #[test]
+    fn diffs_changed_fields() {
+        let before = doc! { "name": "alice", "age": 30, "role": "admin" };
+        let after = doc! { "name": "alice", "age": 31, "team": "core" };
+        let changes = diff_documents(Some(&before), Some(&after));
+        let expected = doc! {
+            "age": { "before": 30, "after": 31 },
+            "role": { "before": "admin" },
+            "team": { "after": "core" },
+        };
+        assert_eq!(changes, expected);
+    }
+
+    #[test]
+    fn diffs_inserts_and_deletes() {
+        let doc = doc! { "name": "alice" };
+        let inserted = diff_documents(None, Some(&doc));
+        assert_eq!(inserted, doc! { "name": { "after": "alice" } });
+        let deleted = diff_documents(Some(&doc), None);
+        assert_eq!(deleted, doc! { "name": { "before": "alice" } });
+    }
+
+    #[test]
+    fn diffs_encrypted_fields_by_plaintext() -> Result<()> {
+        with_keys(|| {
+            let before = doc! { "secret": encrypted("a")?, "other": "x" };
+            let resealed = doc! { "secret": encrypted("a")?, "other": "x" };
+            let changed = doc! { "secret": encrypted("b")?, "other": "x" };
+
+            let changes = diff_documents(Some(&before), Some(&resealed));
+            assert!(changes.is_empty());
+            let changes = diff_documents(Some(&before), Some(&changed));
+            assert_eq!(changes, doc! { "secret": { "encrypted": true } });
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn reveals_nested_encrypted_values() -> Result<()> {
+        with_keys(|| {
+            let value = Bson::from(doc! { "names": [encrypted("a")?, "b"] });
+            let (revealed, encrypted) = reveal(Some(&value));
+            let expected = Bson::from(doc! { "names": ["a", "b"] });
+            assert_eq!(revealed, Some(expected));
+            assert!(encrypted);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn reveals_plain_values_as_is() {
+        let value = Bson::from(doc! { "name": "alice" });
+        let (revealed, encrypted) = reveal(Some(&value));
+        assert_eq!(revealed, Some(value));
+        assert!(!encrypted);
+        assert_eq!(reveal(None), (None, false));
+    }
+
+    #[test]
+    fn checks_nested_fields_are_set() {
+        let doc = doc! { "meta": { "discardedAt": 1, "restoredAt": null } };
+        assert!(is_set(&doc, "meta.discardedAt"));
+        assert!(!is_set(&doc, "meta.restoredAt"));
+        assert!(!is_set(&doc, "meta.missing"));
+        assert!(is_set(&doc, "meta"));
+        assert!(!is_set(&doc, "missing.discardedAt"));
+    }
+}
@@ -0,0 +1,360 @@
+use super::*;
+
+use mongodb::error::ErrorKind as DatabaseErrorKind;
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+
+const BULK_WRITE_CHUNK_SIZE: usize = 1000;
+
+/// The maximum encoded size of the documents sent in a single write command,
+/// leaving room for the rest of the command under the 16MiB limit.
+const BULK_WRITE_CHUNK_BYTES: usize = 15 * 1024 * 1024;
+
+/// The outcome of a bulk write.
+#[derive(Debug, Default)]
+pub struct BulkWriteReport {
+    /// The number of entities that were written.
+    pub written: usize,
+
+    /// The entities that were skipped, because they weren't allowed to be
+    /// written, or failed validation, serialization or their `before_save`
+    /// callback.
+    pub failures: Vec<BulkWriteFailure>,
+}
+
+impl BulkWriteReport {
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct BulkWriteFailure {
+    /// The position of the failed entity in the slice passed to the write.
+    pub index: usize,
+    pub error: Error,
+}
+
+/// The error returned when the database rejects entities in a bulk write
+/// (i.e. because of a duplicate key).
+///
+/// Such errors abort the transaction, so none of the entities are written.
+#[derive(Debug)]
+pub struct BulkWriteError {
+    pub failures: Vec<BulkWriteFailure>,
+}
+
+impl Display for BulkWriteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "failed to write {} entities", self.failures.len())?;
+        if let Some(BulkWriteFailure { index, error }) = self.failures.first() {
+            write!(f, " (entity {}: {})", index, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for BulkWriteError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BulkWriteMode {
+    Insert,
+    Upsert,
+}
+
+pub(super) async fn write_many<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+    entities: &mut [T],
+    mode: BulkWriteMode,
+) -> Result<BulkWriteReport> {
+    ctx.with_transaction(|ctx, transaction| async move {
+        let collection = T::collection(&ctx);
+        let mut report = BulkWriteReport::default();
+
+        let mut pending = Vec::with_capacity(entities.len());
+        for (index, entity) in entities.iter_mut().enumerate() {
+            let result: Result<Document> = async {
+                authorize_write(&ctx, &*entity, WriteAction::Save)?;
+                entity.validate().context("validation failed")?;
+                entity.before_save(&ctx).await?;
+                let doc = ctx
+                    .with_encryption(|| entity.to_document())
+                    .context("failed to serialize record")?;
+                check_sealed(&doc)?;
+                Ok(doc)
+            }
+            .await;
+            match result {
+                Ok(doc) => pending.push((index, doc)),
+                Err(error) => {
+                    report.failures.push(BulkWriteFailure { index, error })
+                }
+            }
+        }
+
+        {
+            let mut transaction = transaction.lock().await;
+            let ids = pending
+                .iter()
+                .map(|&(index, _)| Bson::from(entities[index].id()))
+                .collect::<Vec<_>>();
+            transaction.track_many(&collection, ids.clone()).await?;
+            let auditor = T::auditor();
+            let priors = {
+                let conditions = doc! { "_id": { "$in": ids } };
+                let session = &mut transaction.session;
+                let mut cursor = collection
+                    .find_with_session(conditions, None, session)
+                    .await
+                    .context("failed to load prior states")?;
+                let mut priors = HashMap::new();
+                while let Some(doc) = cursor.next(session).await {
+                    let doc = doc.context("failed to load prior state")?;
+                    let id = doc.get("_id").context("missing document id")?;
+                    priors.insert(id.to_string(), doc);
+                }
+                priors
+            };
+
+            // An entity may have been given the id of another, which it
+            // would overwrite.
+            if mode == BulkWriteMode::Upsert {
+                pending.retain(|&(index, _)| {
+                    let id = Bson::from(entities[index].id()).to_string();
+                    let prior = match priors.get(&id) {
+                        Some(prior) => prior,
+                        None => return true,
+                    };
+                    let action = WriteAction::Save;
+                    match authorize_prior::<T>(&ctx, prior, action) {
+                        Ok(()) => true,
+                        Err(error) => {
+                            let failure = BulkWriteFailure { index, error };
+                            report.failures.push(failure);
+                            false
+                        }
+                    }
+                });
+                report.failures.sort_by_key(|failure| failure.index);
+            }
+
+            let Transaction {
+                session,
+                commit_finalizers,
+                abort_finalizers,
+                ..
+            } = &mut *transaction;
+            for &(index, _) in &pending {
+                {
+                    let entity = entities[index].clone();
+                    let ctx = ctx.clone();
+                    let finalizer =
+                        async move { entity.after_save_commit(&ctx).await };
+                    commit_finalizers.push(finalizer.boxed());
+                }
+                {
+                    let entity = entities[index].clone();
+                    let ctx = ctx.clone();
+                    let finalizer =
+                        async move { entity.after_save_abort(&ctx).await };
+                    abort_finalizers.push(finalizer.boxed());
+                }
+            }
+
+            for chunk in chunk_documents(&pending)? {
+                let docs = chunk.iter().map(|(_, doc)| doc.to_owned());
+                // Indices of write errors are relative to the chunk.
+                let write_errors: Vec<(usize, String)> = match mode {
+                    BulkWriteMode::Insert => {
+                        trace!(
+                            collection = collection.name(),
+                            count = chunk.len(),
+                            "inserting documents"
+                        );
+                        let result = collection
+                            .insert_many_with_session(docs, None, session)
+                            .await;
+                        match result {
+                            Ok(_) => Vec::new(),
+                            Err(error) => match error.kind.as_ref() {
+                                DatabaseErrorKind::BulkWrite(failure) => {
+                                    failure
+                                        .write_errors
+                                        .iter()
+                                        .flatten()
+                                        .map(|error| {
+                                            let message = format!(
+                                                "{} (code {})",
+                                                error.message, error.code
+                                            );
+                                            (error.index, message)
+                                        })
+                                        .collect()
+                                }
+                                _ => return Err(error.into()),
+                            },
+                        }
+                    }
+                    BulkWriteMode::Upsert => {
+                        trace!(
+                            collection = collection.name(),
+                            count = chunk.len(),
+                            "saving documents"
+                        );
+                        let updates = docs
+                            .map(|doc| {
+                                let id = doc.get("_id").cloned();
+                                doc! {
+                                    "q": { "_id": id },
+                                    "u": doc,
+                                    "upsert": true,
+                                }
+                            })
+                            .collect::<Vec<_>>();
+                        let command = doc! {
+                            "update": collection.name(),
+                            "updates": updates,
+                        };
+                        let database = {
+                            let namespace = collection.namespace();
+                            ctx.database_client().database(&namespace.db)
+                        };
+                        let reply = database
+                            .run_command_with_session(command, None, session)
+                            .await?;
+                        let errors = match reply.get_array("writeErrors") {
+                            Ok(errors) => errors.as_slice(),
+                            Err(_) => &[],
+                        };
+                        errors
+                            .iter()
+                            .map(|error| -> Result<_> {
+                                let error = error
+                                    .as_document()
+                                    .context("invalid write error")?;
+                                let index = error
+                                    .get_i32("index")
+                                    .context("invalid write error")?;
+                                let message = format!(
+                                    "{} (code {})",
+                                    error.get_str("errmsg").unwrap_or_default(),
+                                    error.get_i32("code").unwrap_or_default(),
+                                );
+                                Ok((index as usize, message))
+                            })
+                            .collect::<Result<_>>()?
+                    }
+                };
+                if !write_errors.is_empty() {
+                    let failures = write_errors
+                        .into_iter()
+                        .filter_map(|(offset, message)| {
+                            let &(index, _) = chunk.get(offset)?;
+                            let error = Error::msg(message);
+                            Some(BulkWriteFailure { index, error })
+                        })
+                        .collect();
+                    return Err(BulkWriteError { failures }.into());
+                }
+            }
+
+            if let Some(auditor) = &auditor {
+                let transaction = &mut *transaction;
+                for (index, after) in &pending {
+                    let id = entities[*index].id();
+                    let before = priors.get(&Bson::from(&id).to_string());
+                    let after = Some(after);
+                    let operation = auditor.classify(before, after);
+                    auditor
+                        .record(&ctx, transaction, id, operation, before, after)
+                        .await?;
+                }
+            }
+        }
+
+        for &(index, _) in &pending {
+            entities[index].after_save(&ctx).await?;
+        }
+        report.written = pending.len();
+        Ok(report)
+    })
+    .await
+}
+
+/// Splits `pending` into chunks that can each be written by a single
+/// command, by count and by encoded size.
+fn chunk_documents(
+    pending: &[(usize, Document)],
+) -> Result<Vec<&[(usize, Document)]>> {
+    let mut chunks = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (offset, (_, doc)) in pending.iter().enumerate() {
+        let doc_size = {
+            let mut data = Vec::new();
+            doc.to_writer(&mut data)
+                .context("failed to serialize record")?;
+            data.len()
+        };
+        let count = offset - start;
+        let is_full = count == BULK_WRITE_CHUNK_SIZE
+            || size + doc_size > BULK_WRITE_CHUNK_BYTES;
+        if count > 0 && is_full {
+            chunks.push(&pending[start..offset]);
+            start = offset;
+            size = 0;
+        }
+        size += doc_size;
+    }
+    if start < pending.len() {
+        chunks.push(&pending[start..]);
+    }
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(count: usize, doc: Document) -> Vec<(usize, Document)> {
+        (0..count).map(|index| (index, doc.clone())).collect()
+    }
+
+    fn chunk_lens(pending: &[(usize, Document)]) -> Result<Vec<usize>> {
+        let chunks = chunk_documents(pending)?;
+        Ok(chunks.iter().map(|chunk| chunk.len()).collect())
+    }
+
+    #[test]
+    fn chunks_nothing() -> Result<()> {
+        assert!(chunk_documents(&[])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn chunks_documents_by_count() -> Result<()> {
+        let pending = pending(2500, doc! { "name": "alice" });
+        assert_eq!(chunk_lens(&pending)?, [1000, 1000, 500]);
+        Ok(())
+    }
+
+    #[test]
+    fn chunks_documents_by_size() -> Result<()> {
+        let large = doc! { "data": "x".repeat(6 * 1024 * 1024) };
+        let pending = pending(5, large);
+        assert_eq!(chunk_lens(&pending)?, [2, 2, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_indices_in_order() -> Result<()> {
+        let pending = pending(1500, doc! {});
+        let chunks = chunk_documents(&pending)?;
+        let indices = chunks
+            .iter()
+            .flat_map(|chunk| chunk.iter().map(|&(index, _)| index))
+            .collect::<Vec<_>>();
+        assert_eq!(indices, (0..1500).collect::<Vec<_>>());
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+use super::*;
+
+use ring::constant_time::verify_slices_are_equal;
+use ring::hmac::{sign as hmac_sign, Key as HmacKey, HMAC_SHA256};
+
+/// The length, in bytes, of the signature appended by [`IdCodec::Signed`].
+const SIGNATURE_LEN: usize = 12;
+
+/// How an [`EntityId`] is encoded as a string (i.e. by its `Display` and
+/// `FromStr` implementations, and when serialized to human-readable formats
+/// like JSON), as returned by [`Entity::id_codec`].
+#[derive(Debug, Clone)]
+pub enum IdCodec {
+    /// The key as-is (i.e. the hex of an `ObjectId`).
+    Plain,
+
+    /// The key after a prefix (i.e. "usr_01H...").
+    Prefixed(&'static str),
+
+    /// URL-safe base64 of the entity name and key (i.e. "VXNlcjo..."), which
+    /// is unique across entity types. This is the default.
+    Global,
+
+    /// Like [`IdCodec::Global`], but with an HMAC signature of the id
+    /// appended, so that ids can't be forged or enumerated by clients.
+    Signed(Arc<[u8]>),
+}
+
+impl Default for IdCodec {
+    fn default() -> Self {
+        IdCodec::Global
+    }
+}
+
+impl IdCodec {
+    pub fn encode<T: Entity>(&self, id: &EntityId<T>) -> String {
+        let key = id.key();
+        match self {
+            IdCodec::Plain => key.to_string(),
+            IdCodec::Prefixed(prefix) => format!("{}{}", prefix, key),
+            IdCodec::Global => encode_global(T::NAME, &key),
+            IdCodec::Signed(secret) => {
+                let id = encode_global(T::NAME, &key);
+                let signature = sign(secret, &id);
+                format!("{}.{}", id, encode_base64(signature))
+            }
+        }
+    }
+
+    pub fn decode<T: Entity>(&self, s: &str) -> Result<EntityId<T>> {
+        let key = match self {
+            IdCodec::Plain => T::Key::parse(s)?,
+            IdCodec::Prefixed(prefix) => {
+                let key = s.strip_prefix(prefix).with_context(|| {
+                    format!("missing prefix: expected {}", prefix)
+                })?;
+                T::Key::parse(key)?
+            }
+            IdCodec::Global => decode_global::<T>(s)?,
+            IdCodec::Signed(secret) => {
+                let (id, signature) =
+                    s.rsplit_once('.').context("missing signature")?;
+                let signature = decode_base64(signature)
+                    .context("failed to decode signature")?;
+                verify_slices_are_equal(&signature, &sign(secret, id))
+                    .map_err(|_| Error::msg("invalid signature"))?;
+                decode_global::<T>(id)?
+            }
+        };
+        Ok(EntityId::from_key(key))
+    }
+
+    /// Encodes `id` in a global form (i.e. for an [`AnyEntityId`]), which is
+    /// signed if this codec signs ids.
+    pub(super) fn encode_global<T: Entity>(&self, id: &EntityId<T>) -> String {
+        match self {
+            IdCodec::Signed(_) => self.encode(id),
+            _ => encode_global(T::NAME, &id.key()),
+        }
+    }
+
+    /// Decodes a global form of an id, as encoded by
+    /// [`IdCodec::encode_global`].
+    pub(super) fn decode_global<T: Entity>(
+        &self,
+        s: &str,
+    ) -> Result<EntityId<T>> {
+        match self {
+            IdCodec::Signed(_) => self.decode(s),
+            _ => {
+                let key = decode_global::<T>(s)?;
+                Ok(EntityId::from_key(key))
+            }
+        }
+    }
+}
+
+fn encode_global<K: EntityKey>(name: &str, key: &K) -> String {
+    encode_base64(format!("{}:{}", name, key))
+}
+
+fn decode_global<T: Entity>(s: &str) -> Result<T::Key> {
+    let s = {
+        let data = decode_base64(s).context("failed to decode base64")?;
+        String::from_utf8_lossy(&data[..]).into_owned()
+    };
+    // Entity names never contain ':', but keys (i.e. strings) may.
+    let (key, entity_name) = match s.split_once(':') {
+        Some((entity_name, key)) => {
+            let key = T::Key::parse(key)?;
+            (key, entity_name.to_owned())
+        }
+        None => bail!("bad format"),
+    };
+    if entity_name != T::NAME {
+        bail!(
+            "incorrect entity name: expected {}, got {}",
+            T::NAME,
+            entity_name
+        );
+    }
+    Ok(key)
+}
+
+fn sign(secret: &[u8], id: &str) -> Vec<u8> {
+    let key = HmacKey::new(HMAC_SHA256, secret);
+    let tag = hmac_sign(&key, id.as_bytes());
+    tag.as_ref()[..SIGNATURE_LEN].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct Widget;
+
+    impl Object for Widget {
+        fn to_document(&self) -> Result<Document> {
+            Ok(Document::new())
+        }
+
+        fn from_document(_: Document) -> Result<Self> {
+            Ok(Widget)
+        }
+    }
+
+    impl Entity for Widget {
+        const NAME: &'static str = "Widget";
+
+        type Key = ObjectId;
+        type Services = Services;
+        type Conditions = EmptyConditions;
+        type Sorting = EmptySorting;
+
+        fn id(&self) -> EntityId<Self> {
+            EntityId::new()
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct Gadget;
+
+    impl Object for Gadget {
+        fn to_document(&self) -> Result<Document> {
+            Ok(Document::new())
+        }
+
+        fn from_document(_: Document) -> Result<Self> {
+            Ok(Gadget)
+        }
+    }
+
+    impl Entity for Gadget {
+        const NAME: &'static str = "Gadget";
+
+        type Key = String;
+        type Services = Services;
+        type Conditions = EmptyConditions;
+        type Sorting = EmptySorting;
+
+        fn id(&self) -> EntityId<Self> {
+            EntityId::new()
+        }
+    }
+
+    fn signed(secret: &[u8]) -> IdCodec {
+        IdCodec::Signed(secret.into())
+    }
+
+    fn round_trip<T: Entity>(codec: &IdCodec, id: &EntityId<T>) -> Result<()> {
+        let encoded = codec.encode(id);
+        assert_eq!(&codec.decode::<T>(&encoded)?, id);
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_ids() -> Result<()> {
+        let id = EntityId::<Widget>::new();
+        round_trip(&IdCodec::Plain, &id)?;
+        round_trip(&IdCodec::Prefixed("wgt_"), &id)?;
+        round_trip(&IdCodec::Global, &id)?;
+        round_trip(&signed(b"secret"), &id)?;
+        Ok(())
+    }
+
+    #[test]
+    fn encodes_plain_and_prefixed_ids_as_keys() {
+        let id = EntityId::<Widget>::new();
+        let key = id.key().to_hex();
+        assert_eq!(IdCodec::Plain.encode(&id), key);
+        assert_eq!(
+            IdCodec::Prefixed("wgt_").encode(&id),
+            format!("wgt_{}", key)
+        );
+    }
+
+    #[test]
+    fn round_trips_string_keys_with_colons() -> Result<()> {
+        let id = EntityId::<Gadget>::from_key("a:b:c".to_owned());
+        round_trip(&IdCodec::Global, &id)?;
+        round_trip(&signed(b"secret"), &id)?;
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_missing_prefixes() {
+        let id = EntityId::<Widget>::new();
+        let encoded = IdCodec::Plain.encode(&id);
+        let decoded = IdCodec::Prefixed("wgt_").decode::<Widget>(&encoded);
+        assert!(decoded.is_err());
+    }
+
+    #[test]
+    fn rejects_global_ids_of_other_entities() {
+        let id = EntityId::<Gadget>::from_key(ObjectId::new().to_hex());
+        let encoded = IdCodec::Global.encode(&id);
+        assert!(IdCodec::Global.decode::<Widget>(&encoded).is_err());
+    }
+
+    #[test]
+    fn rejects_unsigned_ids() {
+        let id = EntityId::<Widget>::new();
+        let encoded = IdCodec::Global.encode(&id);
+        assert!(signed(b"secret").decode::<Widget>(&encoded).is_err());
+    }
+
+    #[test]
+    fn rejects_ids_signed_with_other_secrets() {
+        let id = EntityId::<Widget>::new();
+        let encoded = signed(b"other").encode(&id);
+        assert!(signed(b"secret").decode::<Widget>(&encoded).is_err());
+    }
+
+    #[test]
+    fn rejects_forged_signatures() {
+        let codec = signed(b"secret");
+        let encoded = codec.encode(&EntityId::<Widget>::new());
+        let (_, signature) = encoded.rsplit_once('.').unwrap();
+        let other = codec.encode(&EntityId::<Widget>::new());
+        let (id, _) = other.rsplit_once('.').unwrap();
+        let forged = format!("{}.{}", id, signature);
+        assert!(codec.decode::<Widget>(&forged).is_err());
+    }
+}
@@ -1,9 +1,18 @@
 use super::*;
 
+use mongodb::error::Error as DatabaseError;
+use mongodb::error::TRANSIENT_TRANSACTION_ERROR;
+use mongodb::options::SessionOptions;
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
 #[derive(Debug)]
 pub struct EntityContext<S: EntityServices> {
     pub(super) services: S,
     pub(super) transaction: Option<Arc<Mutex<Transaction>>>,
+    pub(super) session: Option<Arc<Mutex<DatabaseSession>>>,
+    pub(super) extensions: Extensions,
 }
 
 impl<S: EntityServices> Clone for EntityContext<S> {
@@ -11,11 +20,15 @@ impl<S: EntityServices> Clone for EntityContext<S> {
         let Self {
             services,
             transaction,
+            session,
+            extensions,
         } = self;
 
         Self {
             services: services.to_owned(),
             transaction: transaction.to_owned(),
+            session: session.to_owned(),
+            extensions: extensions.to_owned(),
         }
     }
 }
@@ -25,12 +38,150 @@ impl<S: EntityServices> EntityContext<S> {
         Self {
             services,
             transaction: None,
+            session: None,
+            extensions: default(),
         }
     }
 
     pub fn services(&self) -> &S {
         &self.services
     }
+
+    /// Returns a context that carries `extension`, replacing any extension
+    /// of the same type.
+    ///
+    /// Extensions are carried over to the contexts derived from this one
+    /// (i.e. by transactions), so callbacks can use them.
+    pub fn with_extension<T>(&self, extension: T) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        let mut ctx = self.to_owned();
+        let extensions = Arc::make_mut(&mut ctx.extensions.0);
+        extensions.insert(TypeId::of::<T>(), Arc::new(extension));
+        ctx
+    }
+
+    pub fn extension<T>(&self) -> Option<&T>
+    where
+        T: Send + Sync + 'static,
+    {
+        let Extensions(extensions) = &self.extensions;
+        let extension = extensions.get(&TypeId::of::<T>())?;
+        extension.downcast_ref()
+    }
+
+    /// Returns a context that attributes the changes made through it to
+    /// `actor` (i.e. in the audit log of [`Audited`] entities).
+    pub fn with_actor(&self, actor: impl Into<String>) -> Self {
+        self.with_extension(Actor(actor.into()))
+    }
+
+    pub fn actor(&self) -> Option<&str> {
+        let Actor(actor) = self.extension()?;
+        Some(actor)
+    }
+
+    /// Returns a context whose entities are stored in the database of
+    /// `tenant`, as given by [`EntityServices::tenant_database`].
+    ///
+    /// Fails if `tenant` would make for an invalid database name (i.e. one
+    /// with a `.` or `/` in it, or that is longer than 63 bytes).
+    ///
+    /// Transactions are bound to the tenant of the context that begins them;
+    /// reading or writing through them with a context of another tenant
+    /// fails.
+    pub fn with_tenant(&self, tenant: impl Into<String>) -> Result<Self> {
+        let tenant = tenant.into();
+        if tenant.is_empty() {
+            bail!("empty tenant");
+        }
+        let database = self.services.tenant_database(&tenant);
+        validate_database_name(database.name())
+            .with_context(|| format!("invalid tenant {:?}", tenant))?;
+        let ctx = self.with_extension(Tenant(tenant));
+        Ok(ctx)
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        let Tenant(tenant) = self.extension()?;
+        Some(tenant)
+    }
+
+    /// The database of the context's tenant, or the services' database if
+    /// there is none.
+    pub fn database(&self) -> Database {
+        let Self { services, .. } = self;
+        match self.tenant() {
+            Some(tenant) => services.tenant_database(tenant),
+            None => services.database().to_owned(),
+        }
+    }
+
+    /// Returns a context whose reads and writes share a causally consistent
+    /// session, so that reads made outside of a transaction observe writes
+    /// made earlier through the same context.
+    ///
+    /// If the context is already bound to a session or transaction, it is
+    /// returned as-is.
+    pub async fn with_session(&self) -> Result<Self> {
+        if self.transaction.is_some() || self.session.is_some() {
+            return Ok(self.to_owned());
+        }
+
+        let Self {
+            services,
+            extensions,
+            ..
+        } = self;
+        let session = {
+            let client = services.database_client();
+            let options =
+                SessionOptions::builder().causal_consistency(true).build();
+            let session = client
+                .start_session(Some(options))
+                .await
+                .context("failed to start database session")?;
+            Arc::new(Mutex::new(session))
+        };
+        let ctx = Self {
+            services: services.clone(),
+            transaction: None,
+            session: Some(session),
+            extensions: extensions.clone(),
+        };
+        Ok(ctx)
+    }
+
+    /// The session that reads and writes through the context go through, if
+    /// any.
+    ///
+    /// Fails if the context is bound to a transaction of another tenant.
+    pub(super) async fn session(&self) -> Result<Option<SessionHandle>> {
+        if let Some(transaction) = &self.transaction {
+            self.check_tenant(transaction).await?;
+            let transaction = transaction.to_owned();
+            return Ok(Some(SessionHandle::Transaction(transaction)));
+        }
+        let session = self.session.to_owned().map(SessionHandle::Causal);
+        Ok(session)
+    }
+
+    async fn check_tenant(
+        &self,
+        transaction: &Mutex<Transaction>,
+    ) -> Result<()> {
+        let tenant = transaction.lock().await.tenant.clone();
+        if tenant.as_deref() != self.tenant() {
+            bail!(
+                "cross-tenant access: transaction belongs to tenant {:?}, \
+                 but context belongs to tenant {:?}",
+                tenant,
+                self.tenant()
+            );
+        }
+        Ok(())
+    }
 }
 
 impl<S: EntityServices> EntityContext<S> {
@@ -42,6 +193,81 @@ impl<S: EntityServices> EntityContext<S> {
         self.with_transaction(|ctx, _| f(ctx)).await
     }
 
+    /// Runs `f` like [`EntityContext::transact`], but if already inside of a
+    /// transaction, treats `f` as a savepoint: should it return an error,
+    /// the documents it wrote are restored to their prior state, and the
+    /// commit and abort finalizers it registered are dropped, while the
+    /// enclosing transaction carries on.
+    ///
+    /// Writes made concurrently through the same transaction while a
+    /// savepoint is open are attributed to that savepoint.
+    pub async fn savepoint<F, T, U>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(Self) -> U,
+        U: Future<Output = Result<T>>,
+    {
+        let transaction = match &self.transaction {
+            Some(transaction) => transaction,
+            None => return self.transact(f).await,
+        };
+
+        transaction.lock().await.begin_scope();
+        let result = f(self.to_owned()).await;
+        let mut transaction = transaction.lock().await;
+        if result.is_ok() {
+            transaction.release_scope();
+        } else {
+            transaction
+                .rollback_scope()
+                .await
+                .context("failed to roll back savepoint")?;
+        }
+        result
+    }
+
+    /// Registers `finalizer` to run after the current transaction commits.
+    ///
+    /// Finalizers run in the order they were registered; errors they return
+    /// are reported through [`EntityServices::on_finalizer_error`] rather
+    /// than failing the (already committed) transaction. Outside of a
+    /// transaction, `finalizer` runs immediately.
+    pub async fn on_commit<F>(&self, finalizer: F)
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        match &self.transaction {
+            Some(transaction) => {
+                let mut transaction = transaction.lock().await;
+                transaction.commit_finalizers.push(finalizer.boxed());
+            }
+            None => self.run_finalizers(vec![finalizer.boxed()]).await,
+        }
+    }
+
+    /// Registers `finalizer` to run after the current transaction aborts.
+    ///
+    /// Finalizers run in the order they were registered; errors they return
+    /// are reported through [`EntityServices::on_finalizer_error`]. Outside
+    /// of a transaction, `finalizer` is dropped, since there is nothing to
+    /// abort.
+    pub async fn on_abort<F>(&self, finalizer: F)
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        if let Some(transaction) = &self.transaction {
+            let mut transaction = transaction.lock().await;
+            transaction.abort_finalizers.push(finalizer.boxed());
+        }
+    }
+
+    async fn run_finalizers(&self, finalizers: Vec<Finalizer>) {
+        for finalizer in finalizers {
+            if let Err(error) = finalizer.await {
+                self.services.on_finalizer_error(error);
+            }
+        }
+    }
+
     pub(super) async fn with_transaction<F, T, U>(&self, f: F) -> Result<T>
     where
         F: FnOnce(Self, Arc<Mutex<Transaction>>) -> U,
@@ -58,36 +284,87 @@ impl<S: EntityServices> EntityContext<S> {
 
         if is_root {
             let result = f(ctx, transaction.clone()).await;
-            if result.is_ok() {
+            let finalizers = if result.is_ok() {
                 let mut transaction = transaction.lock().await;
-                transaction.commit().await?;
+                let finalizers = transaction.commit().await?;
+                if let Some(session) = &self.session {
+                    let mut session = session.lock().await;
+                    advance_session(&mut session, &transaction.session);
+                }
+                finalizers
             } else {
                 let mut transaction = transaction.lock().await;
-                transaction.abort().await?;
-            }
+                transaction.abort().await?
+            };
+            self.run_finalizers(finalizers).await;
             result
         } else {
             f(ctx, transaction).await
         }
     }
 
+    /// Like [`EntityContext::with_transaction`], but retries `f` in a new
+    /// transaction when it fails with a transient transaction error (i.e. a
+    /// write conflict with a concurrent transaction).
+    ///
+    /// Within an enclosing transaction, `f` runs only once, since only the
+    /// enclosing transaction as a whole could be retried.
+    pub(super) async fn with_retried_transaction<F, T, U>(
+        &self,
+        f: F,
+    ) -> Result<T>
+    where
+        F: Fn(Self, Arc<Mutex<Transaction>>) -> U,
+        U: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match self.with_transaction(&f).await {
+                Err(error)
+                    if self.transaction.is_none()
+                        && attempt < MAX_TRANSACTION_ATTEMPTS
+                        && is_transient_transaction_error(&error) =>
+                {
+                    warn!(attempt, %error, "retrying transaction");
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
     async fn init_transaction(&self) -> Result<TransactionState<S>> {
         let state = match &self.transaction {
-            Some(transaction) => TransactionState {
-                ctx: self.to_owned(),
-                transaction: transaction.to_owned(),
-                is_root: false,
-            },
+            Some(transaction) => {
+                self.check_tenant(transaction).await?;
+                TransactionState {
+                    ctx: self.to_owned(),
+                    transaction: transaction.to_owned(),
+                    is_root: false,
+                }
+            }
             None => {
-                let Self { services, .. } = self;
+                let Self {
+                    services,
+                    session,
+                    extensions,
+                    ..
+                } = self;
                 let transaction = {
                     let client = services.database_client();
-                    let transaction = Transaction::new(client).await?;
+                    let mut transaction = Transaction::new(client).await?;
+                    transaction.tenant = self.tenant().map(ToOwned::to_owned);
+                    if let Some(session) = session {
+                        let session = session.lock().await;
+                        advance_session(&mut transaction.session, &session);
+                    }
                     Arc::new(Mutex::new(transaction))
                 };
                 let ctx = Self {
                     services: services.clone(),
                     transaction: Some(transaction.clone()),
+                    session: session.clone(),
+                    extensions: extensions.clone(),
                 };
                 TransactionState {
                     ctx,
@@ -107,3 +384,81 @@ impl<S: EntityServices> Deref for EntityContext<S> {
         self.services()
     }
 }
+
+const MAX_TRANSACTION_ATTEMPTS: u32 = 5;
+
+fn is_transient_transaction_error(error: &Error) -> bool {
+    error
+        .chain()
+        .any(|error| match error.downcast_ref::<DatabaseError>() {
+            Some(error) => error.contains_label(TRANSIENT_TRANSACTION_ERROR),
+            None => false,
+        })
+}
+
+#[derive(Clone, Default)]
+pub(super) struct Extensions(Arc<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>);
+
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Self(extensions) = self;
+        f.debug_struct("Extensions")
+            .field("len", &extensions.len())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct Actor(String);
+
+#[derive(Debug)]
+struct Tenant(String);
+
+/// Characters that MongoDB doesn't allow in database names.
+const INVALID_DATABASE_NAME_CHARS: &[char] = &[
+    '/', '\\', '.', ' ', '"', '$', '*', '<', '>', ':', '|', '?', '\0',
+];
+
+const MAX_DATABASE_NAME_LEN: usize = 63;
+
+fn validate_database_name(name: &str) -> Result<()> {
+    if let Some(c) = name
+        .chars()
+        .find(|c| INVALID_DATABASE_NAME_CHARS.contains(c))
+    {
+        bail!("database name {:?} contains {:?}", name, c);
+    }
+    if name.len() > MAX_DATABASE_NAME_LEN {
+        bail!(
+            "database name {:?} is longer than {} bytes",
+            name,
+            MAX_DATABASE_NAME_LEN
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_database_names() -> Result<()> {
+        validate_database_name("app_acme")?;
+        validate_database_name(&"a".repeat(MAX_DATABASE_NAME_LEN))?;
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_database_names_with_invalid_chars() {
+        for name in ["app/acme", "app.acme", "app acme", "app$", "app\0"] {
+            assert!(validate_database_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_long_database_names() {
+        let name = "a".repeat(MAX_DATABASE_NAME_LEN + 1);
+        assert!(validate_database_name(&name).is_err());
+    }
+}
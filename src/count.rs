@@ -0,0 +1,191 @@
+use super::*;
+
+use mongodb::options::AggregateOptions;
+use mongodb::options::CountOptions;
+
+use futures_util::TryStreamExt;
+
+/// How [`Entity::count`] counts entities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountMode {
+    /// Counts the documents in the entity's scope, as seen by the context's
+    /// session (if any).
+    Exact,
+
+    /// Estimates the number of documents in the collection from its
+    /// metadata, which is fast but may be inaccurate (i.e. after an unclean
+    /// shutdown).
+    ///
+    /// Falls back to an exact count where an estimate would be wrong: when
+    /// the entity has a default scope or a read filter, or within a session.
+    Estimated,
+}
+
+/// Counts the documents of `T` that match `conditions`.
+pub(super) async fn count_documents<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+    conditions: Option<Document>,
+    options: CountOptions,
+) -> Result<u64> {
+    let collection = T::collection(ctx);
+    let filter = conditions.clone().unwrap_or_default();
+    let count = if let Some(session) = ctx.session().await? {
+        let mut session = session.lock().await;
+        trace!(
+            collection = collection.name(),
+            session = %session.id(),
+            conditions = %filter,
+            skip = ?options.skip,
+            limit = ?options.limit,
+            "counting documents"
+        );
+        collection
+            .count_documents_with_session(conditions, options, &mut session)
+            .await?
+    } else {
+        trace!(
+            collection = collection.name(),
+            conditions = %filter,
+            skip = ?options.skip,
+            limit = ?options.limit,
+            "counting documents"
+        );
+        collection.count_documents(conditions, options).await?
+    };
+    Ok(count)
+}
+
+/// Estimates the number of documents of `T`, if an estimate would be
+/// accurate; see [`CountMode::Estimated`].
+pub(super) async fn estimate_documents<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+) -> Result<Option<u64>> {
+    let scoped = scope_conditions::<T>(ctx, None, true)?.is_some();
+    if scoped || ctx.session().await?.is_some() {
+        return Ok(None);
+    }
+    let collection = T::collection(ctx);
+    trace!(collection = collection.name(), "estimating document count");
+    let count = collection.estimated_document_count(None).await?;
+    Ok(Some(count))
+}
+
+/// Counts the documents output by `pipeline`.
+pub(super) async fn count_aggregated<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+    pipeline: Vec<Document>,
+    options: AggregateOptions,
+) -> Result<u64> {
+    let collection = T::collection(ctx);
+    let pipeline = {
+        let mut pipeline = pipeline;
+        pipeline.push(doc! {
+            "$count": "_count"
+        });
+        pipeline
+    };
+
+    // $count outputs no document at all when there is nothing to count.
+    let result: Option<Document> = if let Some(session) = ctx.session().await? {
+        let mut session = session.lock().await;
+        trace!(
+            collection = collection.name(),
+            session = %session.id(),
+            pipeline = %format_pipeline(&pipeline),
+            options = %format_aggregate_options(&options),
+            "counting aggregated documents"
+        );
+        let mut cursor = {
+            collection
+                .aggregate_with_session(pipeline, options, &mut session)
+                .await?
+        };
+        cursor.next(&mut session).await.transpose()?
+    } else {
+        trace!(
+            collection = collection.name(),
+            pipeline = %format_pipeline(&pipeline),
+            options = %format_aggregate_options(&options),
+            "counting aggregated documents"
+        );
+        let mut cursor = collection.aggregate(pipeline, options).await?;
+        cursor.next().await.transpose()?
+    };
+
+    let count = match result {
+        Some(result) => get_count(&result, "_count")?,
+        None => 0,
+    };
+    Ok(count)
+}
+
+/// Counts the documents output by `pipeline` for each distinct value of
+/// `field`, most frequent first.
+pub(super) async fn count_grouped<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+    pipeline: Vec<Document>,
+    field: &str,
+    options: AggregateOptions,
+) -> Result<Vec<(Bson, u64)>> {
+    let collection = T::collection(ctx);
+    let pipeline = {
+        let mut pipeline = pipeline;
+        let path = format!("${}", field);
+        pipeline.push(doc! {
+            "$group": { "_id": path, "_count": { "$sum": 1 } }
+        });
+        pipeline.push(doc! {
+            "$sort": { "_count": -1, "_id": 1 }
+        });
+        pipeline
+    };
+
+    let docs: Vec<Document> = if let Some(session) = ctx.session().await? {
+        let mut session = session.lock().await;
+        trace!(
+            collection = collection.name(),
+            session = %session.id(),
+            pipeline = %format_pipeline(&pipeline),
+            options = %format_aggregate_options(&options),
+            "counting grouped documents"
+        );
+        let mut cursor = {
+            collection
+                .aggregate_with_session(pipeline, options, &mut session)
+                .await?
+        };
+        let mut docs = Vec::new();
+        while let Some(doc) = cursor.next(&mut session).await {
+            docs.push(doc?);
+        }
+        docs
+    } else {
+        trace!(
+            collection = collection.name(),
+            pipeline = %format_pipeline(&pipeline),
+            options = %format_aggregate_options(&options),
+            "counting grouped documents"
+        );
+        let cursor = collection.aggregate(pipeline, options).await?;
+        cursor.try_collect().await?
+    };
+
+    docs.into_iter()
+        .map(|mut doc| {
+            let count = get_count(&doc, "_count")?;
+            let value = doc.remove("_id").unwrap_or(Bson::Null);
+            Ok((value, count))
+        })
+        .collect()
+}
+
+/// Reads the count at `key`, which is an int32 or an int64 depending on its
+/// magnitude.
+fn get_count(doc: &Document, key: &str) -> Result<u64> {
+    let count = doc
+        .get_i64(key)
+        .or_else(|_| doc.get_i32(key).map(i64::from))
+        .context("missing count")?;
+    let count = u64::try_from(count).context("invalid count")?;
+    Ok(count)
+}
@@ -1,3 +1,4 @@
+pub use mongodb::options::ReturnDocument;
 pub use mongodb::Client as DatabaseClient;
 pub use mongodb::ClientSession as DatabaseSession;
 pub use mongodb::{Collection, Database};
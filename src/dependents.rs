@@ -0,0 +1,325 @@
+use super::*;
+
+use std::collections::HashSet;
+use std::error::Error as StdError;
+
+/// The maximum number of blocking ids listed in a [`DependentReference`].
+const RESTRICT_REFERENCE_LIMIT: u64 = 10;
+
+/// What happens to dependent entities when the entity they reference is
+/// deleted or discarded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependentPolicy {
+    /// Deletes dependents along with the entity. Dependents are left alone
+    /// when the entity is discarded.
+    Cascade,
+
+    /// Unsets the referencing field of dependents when the entity is
+    /// deleted. Dependents are left alone when the entity is discarded.
+    Nullify,
+
+    /// Refuses to delete or discard the entity while dependents exist,
+    /// failing with a [`RestrictedError`].
+    ///
+    /// When discarding, only dependents matched by their default scope (i.e.
+    /// those which haven't been discarded themselves) are considered.
+    Restrict,
+
+    /// Discards dependents when the entity is deleted or discarded.
+    Discard,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum DependentEvent {
+    Delete,
+    Discard,
+}
+
+type DependentHandler<T> =
+    for<'a> fn(
+        &'a EntityContext<<T as Entity>::Services>,
+        &'a str,
+        EntityId<T>,
+        DependentEvent,
+    ) -> BoxFuture<'a, Result<Option<DependentReference>>>;
+
+/// A relationship in which entities of another type reference an entity of
+/// type `T` through a field, declared by [`Entity::dependents`].
+///
+/// The field is expected to store the referenced id the way `_id` is stored
+/// (i.e. as the BSON form of its [`EntityKey`]).
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+pub struct Dependent<T: Entity> {
+    entity: &'static str,
+    field: String,
+    policy: DependentPolicy,
+
+    #[derivative(Debug = "ignore")]
+    handler: DependentHandler<T>,
+}
+
+impl<T: Entity> Dependent<T> {
+    pub fn cascade<D>(field: impl Into<String>) -> Self
+    where
+        D: Entity<Services = T::Services>,
+    {
+        let handler = cascade_dependents::<T, D>;
+        Self::new::<D>(field, DependentPolicy::Cascade, handler)
+    }
+
+    pub fn nullify<D>(field: impl Into<String>) -> Self
+    where
+        D: Entity<Services = T::Services>,
+    {
+        let handler = nullify_dependents::<T, D>;
+        Self::new::<D>(field, DependentPolicy::Nullify, handler)
+    }
+
+    pub fn restrict<D>(field: impl Into<String>) -> Self
+    where
+        D: Entity<Services = T::Services>,
+    {
+        let handler = restrict_dependents::<T, D>;
+        Self::new::<D>(field, DependentPolicy::Restrict, handler)
+    }
+
+    pub fn discard<D>(field: impl Into<String>) -> Self
+    where
+        D: Discardable<Services = T::Services>,
+    {
+        let handler = discard_dependents::<T, D>;
+        Self::new::<D>(field, DependentPolicy::Discard, handler)
+    }
+
+    fn new<D: Entity>(
+        field: impl Into<String>,
+        policy: DependentPolicy,
+        handler: DependentHandler<T>,
+    ) -> Self {
+        Self {
+            entity: D::NAME,
+            field: field.into(),
+            policy,
+            handler,
+        }
+    }
+
+    pub fn entity(&self) -> &'static str {
+        self.entity
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn policy(&self) -> DependentPolicy {
+        self.policy
+    }
+}
+
+/// Enforces the dependents of `entity` ahead of `event`, after its `before_`
+/// callback has run.
+///
+/// Restrictions are checked before any other policy is applied, so that
+/// nothing is written when the event is refused. Each entity is handled at
+/// most once per event while enforcing a chain of dependents, so that cyclic
+/// dependents (i.e. cascading back to `entity`) don't recurse without end.
+pub(super) async fn handle_dependents<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+    entity: &T,
+    event: DependentEvent,
+) -> Result<()> {
+    let dependents = T::dependents();
+    if dependents.is_empty() {
+        return Ok(());
+    }
+    let id = entity.id();
+    let ctx = &match ctx.extension::<HandledDependents>() {
+        Some(_) => ctx.to_owned(),
+        None => ctx.with_extension(HandledDependents::default()),
+    };
+    if let Some(HandledDependents(handled)) = ctx.extension() {
+        let key = format!("{:?}:{}:{}", event, T::NAME, id);
+        if !handled.lock().await.insert(key) {
+            return Ok(());
+        }
+    }
+    let (restricted, others): (Vec<_>, Vec<_>) = dependents
+        .iter()
+        .partition(|dependent| dependent.policy == DependentPolicy::Restrict);
+
+    let mut references = Vec::new();
+    for Dependent { field, handler, .. } in restricted {
+        if let Some(reference) = handler(ctx, field, id.clone(), event).await? {
+            references.push(reference);
+        }
+    }
+    if !references.is_empty() {
+        let error = RestrictedError {
+            entity: T::NAME,
+            id: id.to_string(),
+            references,
+        };
+        return Err(error.into());
+    }
+
+    for Dependent { field, handler, .. } in others {
+        handler(ctx, field, id.clone(), event).await?;
+    }
+    Ok(())
+}
+
+/// The entities whose dependents have been handled (keyed by event, entity
+/// name and id) in the chain of dependents being enforced by a context.
+#[derive(Debug, Default)]
+struct HandledDependents(Mutex<HashSet<String>>);
+
+/// Finds the dependents of type `D` that reference `id` at `field`.
+///
+/// Every dependent is found regardless of the read policy of `D`, since
+/// those hidden from the actor would otherwise be left dangling (or fail to
+/// restrict the event).
+fn dependents_of<T: Entity, D: Entity>(
+    field: &str,
+    id: EntityId<T>,
+) -> FindQuery<D> {
+    FindQuery::new_untyped(doc! { field: id }).unfiltered()
+}
+
+fn cascade_dependents<'a, T, D>(
+    ctx: &'a EntityContext<T::Services>,
+    field: &'a str,
+    id: EntityId<T>,
+    event: DependentEvent,
+) -> BoxFuture<'a, Result<Option<DependentReference>>>
+where
+    T: Entity,
+    D: Entity<Services = T::Services>,
+{
+    async move {
+        if event == DependentEvent::Delete {
+            let query = dependents_of::<T, D>(field, id).unscoped();
+            query.delete_all(ctx).await?;
+        }
+        Ok(None)
+    }
+    .boxed()
+}
+
+fn nullify_dependents<'a, T, D>(
+    ctx: &'a EntityContext<T::Services>,
+    field: &'a str,
+    id: EntityId<T>,
+    event: DependentEvent,
+) -> BoxFuture<'a, Result<Option<DependentReference>>>
+where
+    T: Entity,
+    D: Entity<Services = T::Services>,
+{
+    async move {
+        if event == DependentEvent::Delete {
+            let query = dependents_of::<T, D>(field, id).unscoped();
+            let update = doc! { "$unset": { field: "" } };
+            query.update_all(ctx, update).await?;
+        }
+        Ok(None)
+    }
+    .boxed()
+}
+
+fn restrict_dependents<'a, T, D>(
+    ctx: &'a EntityContext<T::Services>,
+    field: &'a str,
+    id: EntityId<T>,
+    event: DependentEvent,
+) -> BoxFuture<'a, Result<Option<DependentReference>>>
+where
+    T: Entity,
+    D: Entity<Services = T::Services>,
+{
+    async move {
+        let query = dependents_of::<T, D>(field, id);
+        let query = match event {
+            DependentEvent::Delete => query.unscoped(),
+            DependentEvent::Discard => query,
+        };
+        let ids = {
+            let stream = query.take(RESTRICT_REFERENCE_LIMIT).load(ctx).await?;
+            pin_mut!(stream);
+            let mut ids = Vec::new();
+            while let Some(entity) = stream.next().await {
+                ids.push(entity?.id().to_string());
+            }
+            ids
+        };
+        if ids.is_empty() {
+            return Ok(None);
+        }
+        let reference = DependentReference {
+            entity: D::NAME,
+            field: field.to_owned(),
+            ids,
+        };
+        Ok(Some(reference))
+    }
+    .boxed()
+}
+
+fn discard_dependents<'a, T, D>(
+    ctx: &'a EntityContext<T::Services>,
+    field: &'a str,
+    id: EntityId<T>,
+    _event: DependentEvent,
+) -> BoxFuture<'a, Result<Option<DependentReference>>>
+where
+    T: Entity,
+    D: Discardable<Services = T::Services>,
+{
+    async move {
+        let query = dependents_of::<T, D>(field, id).unscoped().kept();
+        D::discard_all(ctx, query).await?;
+        Ok(None)
+    }
+    .boxed()
+}
+
+/// Dependents that prevented an entity from being deleted or discarded.
+#[derive(Debug, Clone)]
+pub struct DependentReference {
+    pub entity: &'static str,
+    pub field: String,
+
+    /// The ids of the blocking dependents, up to a limit of 10.
+    pub ids: Vec<String>,
+}
+
+/// The error returned when deleting or discarding an entity is refused by a
+/// [`DependentPolicy::Restrict`] dependent.
+#[derive(Debug, Clone)]
+pub struct RestrictedError {
+    pub entity: &'static str,
+    pub id: String,
+    pub references: Vec<DependentReference>,
+}
+
+impl Display for RestrictedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Self {
+            entity,
+            id,
+            references,
+        } = self;
+        write!(f, "{} {} is referenced by ", entity, id)?;
+        for (index, reference) in references.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            let DependentReference { entity, field, ids } = reference;
+            write!(f, "{}.{} ({})", entity, field, ids.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for RestrictedError {}
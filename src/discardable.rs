@@ -1,46 +1,71 @@
 use super::*;
 
-/// TODO: Find a better way to traverse paths (i.e. "discardedAt").
-/// Maybe try using [`frunk`][frunk]?
+/// An entity that can be discarded (soft-deleted) and later restored, rather
+/// than deleted.
 ///
-/// [frunk]: https://github.com/lloydmeta/frunk
+/// Discarded entities are left out of queries once a [`Discarder`] is
+/// returned from [`Entity::discarder`].
 #[async_trait]
 pub trait Discardable: Entity {
-    fn as_discardable(&self) -> DiscardableView;
-    fn as_discardable_mut(&mut self) -> DiscardableViewMut;
+    fn as_discardable(&self) -> DiscardableView<'_>;
+    fn as_discardable_mut(&mut self) -> DiscardableViewMut<'_>;
 
     fn is_discarded(&self) -> bool {
         let view = self.as_discardable();
         view.discarded_at.is_some()
     }
 
-    fn kept() -> FindQuery<Self> {
-        FindQuery::new_untyped(doc! {
-            "discardedAt": {
-                "$exists": false
+    /// The path of the field that stores the discard timestamp.
+    ///
+    /// Defaults to the name [`DiscardableView`] serializes it with; override
+    /// this if the timestamp is stored elsewhere (i.e. in a nested document).
+    fn discarded_at_path() -> String {
+        DiscardableView::discarded_at_path()
+    }
+
+    /// Conditions that match entities which haven't been discarded, i.e.
+    /// whose discard timestamp is null or missing.
+    ///
+    /// These are part of the default scope of entities that return a
+    /// [`Discarder`] from [`Entity::discarder`].
+    fn kept_conditions() -> Document {
+        let path = Self::discarded_at_path();
+        doc! { path: null }
+    }
+
+    /// Conditions that match entities which have been discarded.
+    fn discarded_conditions() -> Document {
+        let path = Self::discarded_at_path();
+        doc! {
+            path: {
+                "$ne": null
             }
-        })
+        }
+    }
+
+    fn kept() -> FindQuery<Self> {
+        Self::all().kept()
     }
 
     fn discarded() -> FindQuery<Self> {
-        FindQuery::new_untyped(doc! {
-            "discardedAt": {
-                "$exists": true
-            }
-        })
+        Self::all().discarded()
     }
 
     async fn discard(
         &mut self,
         ctx: &EntityContext<Self::Services>,
     ) -> Result<()> {
-        let view = self.as_discardable_mut();
-        *view.discarded_at = Some(now());
+        ctx.with_transaction(|ctx, _| async move {
+            let view = self.as_discardable_mut();
+            *view.discarded_at = Some(now());
 
-        self.before_discard(ctx).await?;
-        self.save(ctx).await?;
-        self.after_discard(ctx).await?;
-        Ok(())
+            self.before_discard(&ctx).await?;
+            handle_dependents(&ctx, &*self, DependentEvent::Discard).await?;
+            self.save(&ctx).await?;
+            self.after_discard(&ctx).await?;
+            Ok(())
+        })
+        .await
     }
 
     async fn discard_without_callbacks(
@@ -78,6 +103,199 @@ pub trait Discardable: Entity {
         Ok(())
     }
 
+    /// Discards every entity matched by `query` with a single update, running
+    /// the discard callbacks of each.
+    ///
+    /// Entities which have already been discarded are left alone. Only the
+    /// discard timestamp is written; changes made to other fields by
+    /// callbacks are not saved.
+    async fn discard_all(
+        ctx: &EntityContext<Self::Services>,
+        query: FindQuery<Self>,
+    ) -> Result<u64> {
+        let query = query.kept();
+        ctx.with_transaction(|ctx, transaction| async move {
+            let discarded_at = now();
+            let mut entities = Vec::new();
+            {
+                let stream = query.load(&ctx).await?;
+                pin_mut!(stream);
+                while let Some(entity) = stream.next().await {
+                    entities.push(entity?);
+                }
+            }
+            if entities.is_empty() {
+                return Ok(0);
+            }
+            for entity in &entities {
+                authorize_write(&ctx, entity, WriteAction::Save)?;
+            }
+            let auditor = Self::auditor();
+            let priors = match &auditor {
+                Some(auditor) => auditor.snapshot(&ctx, &entities)?,
+                None => Vec::new(),
+            };
+
+            for entity in &mut entities {
+                let view = entity.as_discardable_mut();
+                *view.discarded_at = Some(discarded_at);
+                entity.before_discard(&ctx).await?;
+            }
+            for entity in &entities {
+                let event = DependentEvent::Discard;
+                handle_dependents(&ctx, entity, event).await?;
+            }
+            let ids = entities.iter().map(Entity::id).collect::<Vec<_>>();
+            let update = {
+                let path = Self::discarded_at_path();
+                let discarded_at = Self::discarded_at_to_bson(&discarded_at)?;
+                doc! { "$set": { path: discarded_at } }
+            };
+            let count = Self::get_many(ids)
+                .unscoped()
+                .update_all_without_audit(&ctx, update)
+                .await?;
+            if let Some(auditor) = &auditor {
+                let mut transaction = transaction.lock().await;
+                let operation = AuditOperation::Discard;
+                auditor
+                    .record_all(
+                        &ctx,
+                        &mut transaction,
+                        &entities,
+                        &priors,
+                        operation,
+                    )
+                    .await?;
+            }
+            for entity in &mut entities {
+                entity.after_discard(&ctx).await?;
+            }
+            Ok(count)
+        })
+        .await
+    }
+
+    /// Discards every entity matched by `query` with a single update, without
+    /// loading them or running their callbacks.
+    ///
+    /// Since entities aren't loaded, only those matching
+    /// [`Entity::write_filter`] are discarded, rather than checking
+    /// [`Entity::can_write`].
+    async fn discard_all_without_callbacks(
+        ctx: &EntityContext<Self::Services>,
+        query: FindQuery<Self>,
+    ) -> Result<u64> {
+        let update = {
+            let path = Self::discarded_at_path();
+            let discarded_at = Self::discarded_at_to_bson(&now())?;
+            doc! { "$set": { path: discarded_at } }
+        };
+        let query = query.kept().writable(ctx, WriteAction::Save);
+        query.update_all(ctx, update).await
+    }
+
+    /// Restores every entity matched by `query` with a single update, running
+    /// the restore callbacks of each.
+    ///
+    /// Only discarded entities are restored (bypassing the discarder, as with
+    /// [`FindQuery::discarded`]). Only the discard timestamp is written;
+    /// changes made to other fields by callbacks are not saved.
+    async fn restore_all(
+        ctx: &EntityContext<Self::Services>,
+        query: FindQuery<Self>,
+    ) -> Result<u64> {
+        let query = query.discarded();
+        ctx.with_transaction(|ctx, transaction| async move {
+            let mut entities = Vec::new();
+            {
+                let stream = query.load(&ctx).await?;
+                pin_mut!(stream);
+                while let Some(entity) = stream.next().await {
+                    entities.push(entity?);
+                }
+            }
+            if entities.is_empty() {
+                return Ok(0);
+            }
+            for entity in &entities {
+                authorize_write(&ctx, entity, WriteAction::Save)?;
+            }
+            let auditor = Self::auditor();
+            let priors = match &auditor {
+                Some(auditor) => auditor.snapshot(&ctx, &entities)?,
+                None => Vec::new(),
+            };
+
+            for entity in &mut entities {
+                let view = entity.as_discardable_mut();
+                *view.discarded_at = None;
+                entity.before_restore(&ctx).await?;
+            }
+            let ids = entities.iter().map(Entity::id).collect::<Vec<_>>();
+            let update = {
+                let path = Self::discarded_at_path();
+                doc! { "$unset": { path: "" } }
+            };
+            let count = Self::get_many(ids)
+                .unscoped()
+                .update_all_without_audit(&ctx, update)
+                .await?;
+            if let Some(auditor) = &auditor {
+                let mut transaction = transaction.lock().await;
+                let operation = AuditOperation::Restore;
+                auditor
+                    .record_all(
+                        &ctx,
+                        &mut transaction,
+                        &entities,
+                        &priors,
+                        operation,
+                    )
+                    .await?;
+            }
+            for entity in &mut entities {
+                entity.after_restore(&ctx).await?;
+            }
+            Ok(count)
+        })
+        .await
+    }
+
+    /// Like [`Discardable::discard_all_without_callbacks`], but restores the
+    /// discarded entities matched by `query`.
+    async fn restore_all_without_callbacks(
+        ctx: &EntityContext<Self::Services>,
+        query: FindQuery<Self>,
+    ) -> Result<u64> {
+        let update = {
+            let path = Self::discarded_at_path();
+            doc! { "$unset": { path: "" } }
+        };
+        let query = query.discarded().writable(ctx, WriteAction::Save);
+        query.update_all(ctx, update).await
+    }
+
+    /// Permanently deletes entities that were discarded before `before`, in
+    /// batches, running the delete callbacks of each.
+    async fn purge_discarded_before(
+        ctx: &EntityContext<Self::Services>,
+        before: DateTime,
+    ) -> Result<u64> {
+        purge_discarded::<Self>(ctx, &before, PURGE_BATCH_SIZE).await
+    }
+
+    /// Converts a discard timestamp to the representation it is stored with
+    /// by [`Object::to_document`], for use in bulk updates and in comparisons
+    /// (i.e. when purging).
+    ///
+    /// This must match the entity's documents exactly, or bulk discards would
+    /// store timestamps that other writes don't, and purges would miss them:
+    /// i.e. a [`bson::DateTime`] if the document converts the timestamp to
+    /// one, or a string if it is serialized through `chrono`'s `serde`
+    /// support.
+    fn discarded_at_to_bson(discarded_at: &DateTime) -> Result<Bson>;
+
     #[allow(unused_variables)]
     async fn before_discard(
         &mut self,
@@ -111,12 +329,98 @@ pub trait Discardable: Entity {
     }
 }
 
+/// Leaves discarded entities of type `T` out of queries.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""), Clone(bound = ""))]
+pub struct Discarder<T: Entity> {
+    #[derivative(Debug = "ignore")]
+    kept_conditions: fn() -> Document,
+    phantom: PhantomData<T>,
+}
+
+impl<T: Discardable> Discarder<T> {
+    pub fn new() -> Self {
+        Self {
+            kept_conditions: T::kept_conditions,
+            phantom: default(),
+        }
+    }
+}
+
+impl<T: Discardable> Default for Discarder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Entity> Discarder<T> {
+    pub(super) fn kept_conditions(&self) -> Document {
+        (self.kept_conditions)()
+    }
+}
+
 #[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct DiscardableView<'a> {
     pub discarded_at: &'a Option<DateTime>,
 }
 
+impl DiscardableView<'_> {
+    /// The name [`DiscardableView::discarded_at`] is serialized with.
+    pub fn discarded_at_path() -> String {
+        let view = DiscardableView {
+            discarded_at: &None,
+        };
+        let doc = to_document(&view).expect("failed to serialize view");
+        let (path, _) = doc.into_iter().next().expect("missing field");
+        path
+    }
+}
+
 #[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct DiscardableViewMut<'a> {
     pub discarded_at: &'a mut Option<DateTime>,
 }
+
+impl<T: Discardable> FindQuery<T> {
+    /// Restricts the query to entities which haven't been discarded.
+    pub fn kept(self) -> Self {
+        self.and_untyped(T::kept_conditions())
+    }
+
+    /// Restricts the query to entities which have been discarded, bypassing
+    /// the discarder of the default scope (which would otherwise exclude
+    /// them).
+    pub fn discarded(self) -> Self {
+        self.and_untyped(T::discarded_conditions()).with_discarded()
+    }
+}
+
+impl<T: Discardable> FindOneQuery<T> {
+    /// Restricts the query to entities which haven't been discarded.
+    pub fn kept(self) -> Self {
+        self.and_untyped(T::kept_conditions())
+    }
+
+    /// Restricts the query to entities which have been discarded, bypassing
+    /// the discarder of the default scope (which would otherwise exclude
+    /// them).
+    pub fn discarded(self) -> Self {
+        self.and_untyped(T::discarded_conditions()).with_discarded()
+    }
+}
+
+impl<T: Discardable> MaybeFindOneQuery<T> {
+    /// Restricts the query to entities which haven't been discarded.
+    pub fn kept(self) -> Self {
+        self.and_untyped(T::kept_conditions())
+    }
+
+    /// Restricts the query to entities which have been discarded, bypassing
+    /// the discarder of the default scope (which would otherwise exclude
+    /// them).
+    pub fn discarded(self) -> Self {
+        self.and_untyped(T::discarded_conditions()).with_discarded()
+    }
+}
@@ -0,0 +1,616 @@
+use super::*;
+
+use bson::spec::BinarySubtype;
+use bson::Binary;
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::aead::{AES_256_GCM, NONCE_LEN};
+use ring::hmac::{sign as hmac_sign, Key as HmacKey, HMAC_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use serde::ser::Error as SerializeError;
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+const ENCRYPTION_VERSION: u8 = 1;
+
+/// The binary subtype of values in conditions that are pending encryption;
+/// see [`seal_conditions`].
+const PENDING_SUBTYPE: u8 = 0x80;
+
+thread_local! {
+    static KEY_PROVIDER: RefCell<Option<Arc<dyn KeyProvider>>> =
+        RefCell::new(None);
+
+    /// Whether entity documents are being (de)serialized, within
+    /// [`with_key_provider`].
+    static IN_DOCUMENT: Cell<bool> = Cell::new(false);
+}
+
+/// A source of the keys used to encrypt and decrypt [`Encrypted`] values.
+pub trait KeyProvider: Debug + Send + Sync {
+    /// The key to encrypt new values with.
+    fn current_key(&self) -> Result<EncryptionKey>;
+
+    /// The key with `id`, to decrypt values that were encrypted with it.
+    fn key(&self, id: &str) -> Result<EncryptionKey>;
+}
+
+/// A 256-bit AES-GCM key, identified by an id that is stored alongside the
+/// values it encrypts.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: String,
+    secret: [u8; 32],
+}
+
+impl EncryptionKey {
+    pub fn new(id: impl Into<String>, secret: [u8; 32]) -> Self {
+        Self {
+            id: id.into(),
+            secret,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Derives a subkey for `purpose`, so that the secret itself is never
+    /// used for more than one algorithm.
+    fn derive(&self, purpose: &str) -> HmacKey {
+        let key = HmacKey::new(HMAC_SHA256, &self.secret);
+        let tag = hmac_sign(&key, purpose.as_bytes());
+        HmacKey::new(HMAC_SHA256, tag.as_ref())
+    }
+
+    fn cipher(&self) -> Result<LessSafeKey> {
+        let key = self.derive("encryption");
+        let secret = hmac_sign(&key, &[]);
+        let key = UnboundKey::new(&AES_256_GCM, secret.as_ref())
+            .map_err(|_| Error::msg("invalid encryption key"))?;
+        Ok(LessSafeKey::new(key))
+    }
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+/// A [`KeyProvider`] backed by a fixed set of keys.
+///
+/// To rotate keys, make the new key current and keep the previous ones
+/// around until every value encrypted with them has been re-saved.
+#[derive(Debug, Clone)]
+pub struct EncryptionKeys {
+    current: String,
+    keys: HashMap<String, EncryptionKey>,
+}
+
+impl EncryptionKeys {
+    pub fn new(current: EncryptionKey) -> Self {
+        let id = current.id.clone();
+        Self {
+            current: id.clone(),
+            keys: once((id, current)).collect(),
+        }
+    }
+
+    /// Adds `key` for decryption only.
+    pub fn with_previous(mut self, key: EncryptionKey) -> Self {
+        self.keys.entry(key.id.clone()).or_insert(key);
+        self
+    }
+}
+
+impl KeyProvider for EncryptionKeys {
+    fn current_key(&self) -> Result<EncryptionKey> {
+        self.key(&self.current)
+    }
+
+    fn key(&self, id: &str) -> Result<EncryptionKey> {
+        let key = self
+            .keys
+            .get(id)
+            .with_context(|| format!("unknown encryption key: {}", id))?;
+        Ok(key.to_owned())
+    }
+}
+
+/// Makes `provider` available to [`Encrypted`] values (de)serialized by `f`.
+///
+/// The documents serialized by `f` are taken to be entity documents, so that
+/// an [`EntityId`] is serialized as its key (rather than as a string).
+pub fn with_key_provider<F, R>(
+    provider: Option<Arc<dyn KeyProvider>>,
+    f: F,
+) -> R
+where
+    F: FnOnce() -> R,
+{
+    struct Reset(Option<Arc<dyn KeyProvider>>, bool);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            KEY_PROVIDER.with(|cell| cell.replace(previous));
+            IN_DOCUMENT.with(|cell| cell.set(self.1));
+        }
+    }
+
+    let previous = KEY_PROVIDER.with(|cell| cell.replace(provider));
+    let in_document = IN_DOCUMENT.with(|cell| cell.replace(true));
+    let _reset = Reset(previous, in_document);
+    f()
+}
+
+/// Whether entity documents are being serialized; see [`with_key_provider`].
+pub(super) fn in_document() -> bool {
+    IN_DOCUMENT.with(Cell::get)
+}
+
+fn current_key_provider() -> Option<Arc<dyn KeyProvider>> {
+    KEY_PROVIDER.with(|cell| cell.borrow().to_owned())
+}
+
+impl<S: EntityServices> EntityContext<S> {
+    /// Runs `f` with the key provider of the context's services available to
+    /// [`Encrypted`] values (de)serialized by it.
+    ///
+    /// Entities are read and written (and [`Deterministic`] values in
+    /// conditions are encrypted) this way automatically.
+    pub fn with_encryption<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        with_key_provider(self.services.key_provider(), f)
+    }
+}
+
+/// How an [`Encrypted`] value is encrypted.
+pub trait EncryptionMode: Send + Sync + 'static {
+    const DETERMINISTIC: bool;
+}
+
+/// Encrypts each value with a random nonce, so that equal values have
+/// different ciphertexts. This is the default.
+#[derive(Debug, Clone, Copy)]
+pub struct Randomized;
+
+impl EncryptionMode for Randomized {
+    const DETERMINISTIC: bool = false;
+}
+
+/// Encrypts equal values (with the same key) to equal ciphertexts, so that
+/// they can be matched by equality queries, at the cost of revealing which
+/// values are equal.
+///
+/// Values encrypted with a previous key no longer match after rotation,
+/// until they are re-saved.
+#[derive(Debug, Clone, Copy)]
+pub struct Deterministic;
+
+impl EncryptionMode for Deterministic {
+    const DETERMINISTIC: bool = true;
+}
+
+/// A value that is stored encrypted with AES-256-GCM, using the key provider
+/// of the [`EntityServices`] it is read and written with.
+#[derive(Derivative)]
+#[derivative(
+    Clone(bound = "T: Clone"),
+    PartialEq(bound = "T: PartialEq"),
+    Eq(bound = "T: Eq"),
+    Default(bound = "T: Default")
+)]
+pub struct Encrypted<T, M: EncryptionMode = Randomized> {
+    value: T,
+    mode: PhantomData<M>,
+}
+
+impl<T, M: EncryptionMode> Encrypted<T, M> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            mode: default(),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T, M: EncryptionMode> From<T> for Encrypted<T, M> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T, M: EncryptionMode> Deref for Encrypted<T, M> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T, M: EncryptionMode> DerefMut for Encrypted<T, M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+impl<T, M: EncryptionMode> Debug for Encrypted<T, M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str("Encrypted(..)")
+    }
+}
+
+impl<T: Serialize, M: EncryptionMode> Encrypted<T, M> {
+    fn seal(&self) -> Result<Binary> {
+        let value =
+            bson::to_bson(&self.value).context("failed to serialize value")?;
+        seal_value(value, M::DETERMINISTIC)
+    }
+}
+
+/// Encrypts `value` with the current key provider.
+fn seal_value(value: Bson, deterministic: bool) -> Result<Binary> {
+    let provider = current_key_provider()
+        .context("no key provider available to encrypt value")?;
+    let key = provider.current_key()?;
+    let mut data = {
+        let mut data = Vec::new();
+        doc! { "v": value }
+            .to_writer(&mut data)
+            .context("failed to serialize value")?;
+        data
+    };
+
+    let header = {
+        let id = key.id.as_bytes();
+        let id_len =
+            u8::try_from(id.len()).context("encryption key id too long")?;
+        let mut header = vec![ENCRYPTION_VERSION, id_len];
+        header.extend_from_slice(id);
+        header
+    };
+    let mut nonce = [0u8; NONCE_LEN];
+    if deterministic {
+        let key = key.derive("nonce");
+        let tag = hmac_sign(&key, &data);
+        nonce.copy_from_slice(&tag.as_ref()[..NONCE_LEN]);
+    } else {
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| Error::msg("failed to generate nonce"))?;
+    }
+    key.cipher()?
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(&header),
+            &mut data,
+        )
+        .map_err(|_| Error::msg("failed to encrypt value"))?;
+
+    let mut bytes = header;
+    bytes.extend_from_slice(&nonce);
+    bytes.append(&mut data);
+    let binary = Binary {
+        subtype: BinarySubtype::Generic,
+        bytes,
+    };
+    Ok(binary)
+}
+
+impl<T: DeserializeOwned, M: EncryptionMode> Encrypted<T, M> {
+    fn open(binary: Binary) -> Result<Self> {
+        let value = open_value(binary)?;
+        let value =
+            bson::from_bson(value).context("failed to deserialize value")?;
+        Ok(Self::new(value))
+    }
+}
+
+/// Decrypts `binary` with the current key provider, if it is an encrypted
+/// value (i.e. to compare the plaintexts of values in the audit log).
+pub(super) fn reveal_value(binary: &Binary) -> Option<Bson> {
+    if binary.subtype != BinarySubtype::Generic {
+        return None;
+    }
+    open_value(binary.to_owned()).ok()
+}
+
+/// Decrypts `binary` with the current key provider.
+fn open_value(binary: Binary) -> Result<Bson> {
+    let provider = current_key_provider()
+        .context("no key provider available to decrypt value")?;
+    let Binary { mut bytes, .. } = binary;
+
+    let (version, id_len) = match bytes[..] {
+        [version, id_len, ..] => (version, usize::from(id_len)),
+        _ => bail!("malformed encrypted value"),
+    };
+    if version != ENCRYPTION_VERSION {
+        bail!("unsupported encrypted value version: {}", version);
+    }
+    let header_len = 2 + id_len;
+    if bytes.len() < header_len + NONCE_LEN {
+        bail!("malformed encrypted value");
+    }
+    let mut data = bytes.split_off(header_len + NONCE_LEN);
+    let nonce = bytes.split_off(header_len);
+    let header = bytes;
+
+    let key = {
+        let id = String::from_utf8_lossy(&header[2..]);
+        provider.key(&id)?
+    };
+    let nonce = Nonce::try_assume_unique_for_key(&nonce)
+        .map_err(|_| Error::msg("malformed encrypted value"))?;
+    let data = key
+        .cipher()?
+        .open_in_place(nonce, Aad::from(&header), &mut data)
+        .map_err(|_| Error::msg("failed to decrypt value"))?;
+
+    let mut doc = Document::from_reader(&data[..])
+        .context("failed to deserialize value")?;
+    let value = doc.remove("v").context("missing value")?;
+    Ok(value)
+}
+
+/// Converts `value` for use in conditions (i.e. with [`Comparison::Eq`]) or
+/// updates.
+///
+/// The value is encrypted once the query runs, with the key provider of its
+/// context; the query fails if it can't be. Documents that are written as is
+/// (i.e. by [`Entity::save`]) are rejected if they hold such a value, since
+/// they should serialize it instead.
+impl<T: Serialize> From<Encrypted<T, Deterministic>> for Bson {
+    fn from(value: Encrypted<T, Deterministic>) -> Self {
+        let pending = match bson::to_bson(&value.value) {
+            Ok(value) => doc! { "v": value },
+            Err(error) => doc! { "e": error.to_string() },
+        };
+        let mut bytes = Vec::new();
+        if pending.to_writer(&mut bytes).is_err() {
+            // Left empty, which fails to decode once the query runs.
+            bytes.clear();
+        }
+        Bson::Binary(Binary {
+            subtype: BinarySubtype::UserDefined(PENDING_SUBTYPE),
+            bytes,
+        })
+    }
+}
+
+/// Encrypts the [`Deterministic`] values in `conditions` (or in an update)
+/// with the key provider of `ctx`, which are left pending by their
+/// conversion to BSON.
+pub(super) fn seal_conditions<S: EntityServices>(
+    ctx: &EntityContext<S>,
+    conditions: Document,
+) -> Result<Document> {
+    ctx.with_encryption(|| seal_pending_document(conditions))
+}
+
+/// Fails if `doc`, which is about to be written as is, holds a
+/// [`Deterministic`] value that was converted to BSON and left pending,
+/// rather than serialized (and so encrypted).
+pub(super) fn check_sealed(doc: &Document) -> Result<()> {
+    if doc.values().any(is_pending) {
+        bail!("document holds an unencrypted value");
+    }
+    Ok(())
+}
+
+fn is_pending(value: &Bson) -> bool {
+    match value {
+        Bson::Binary(Binary {
+            subtype: BinarySubtype::UserDefined(PENDING_SUBTYPE),
+            ..
+        }) => true,
+        Bson::Document(doc) => doc.values().any(is_pending),
+        Bson::Array(values) => values.iter().any(is_pending),
+        _ => false,
+    }
+}
+
+fn seal_pending_document(doc: Document) -> Result<Document> {
+    doc.into_iter()
+        .map(|(key, value)| Ok((key, seal_pending(value)?)))
+        .collect()
+}
+
+fn seal_pending(value: Bson) -> Result<Bson> {
+    let value = match value {
+        Bson::Binary(Binary {
+            subtype: BinarySubtype::UserDefined(PENDING_SUBTYPE),
+            bytes,
+        }) => {
+            let mut pending = Document::from_reader(&bytes[..])
+                .context("malformed pending encrypted value")?;
+            if let Ok(error) = pending.get_str("e") {
+                bail!("failed to serialize value: {}", error);
+            }
+            let value = pending.remove("v").context("missing value")?;
+            let binary =
+                seal_value(value, true).context("failed to encrypt value")?;
+            Bson::Binary(binary)
+        }
+        Bson::Document(doc) => Bson::Document(seal_pending_document(doc)?),
+        Bson::Array(values) => {
+            let values = values
+                .into_iter()
+                .map(seal_pending)
+                .collect::<Result<_>>()?;
+            Bson::Array(values)
+        }
+        value => value,
+    };
+    Ok(value)
+}
+
+impl<T: Serialize, M: EncryptionMode> Serialize for Encrypted<T, M> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let binary = self.seal().map_err(|error| {
+            let message = format!("{:#}", error);
+            S::Error::custom(message)
+        })?;
+        binary.serialize(serializer)
+    }
+}
+
+impl<'de, T, M> Deserialize<'de> for Encrypted<T, M>
+where
+    T: DeserializeOwned,
+    M: EncryptionMode,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let binary = Binary::deserialize(deserializer)?;
+        Self::open(binary).map_err(|error| {
+            let message = format!("{:#}", error);
+            D::Error::custom(message)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(key: EncryptionKey) -> Option<Arc<dyn KeyProvider>> {
+        Some(Arc::new(EncryptionKeys::new(key)))
+    }
+
+    fn key() -> EncryptionKey {
+        EncryptionKey::new("current", [1; 32])
+    }
+
+    fn seal<M: EncryptionMode>(value: &str) -> Result<Bson> {
+        let value = Encrypted::<_, M>::new(value.to_owned());
+        let bson = bson::to_bson(&value)?;
+        Ok(bson)
+    }
+
+    fn open<M: EncryptionMode>(bson: Bson) -> Result<String> {
+        let value: Encrypted<String, M> = bson::from_bson(bson)?;
+        Ok(value.into_inner())
+    }
+
+    #[test]
+    fn round_trips_randomized_values() -> Result<()> {
+        with_key_provider(provider(key()), || {
+            let sealed = seal::<Randomized>("secret")?;
+            assert!(matches!(&sealed, Bson::Binary(_)));
+            assert_eq!(open::<Randomized>(sealed)?, "secret");
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn round_trips_deterministic_values() -> Result<()> {
+        with_key_provider(provider(key()), || {
+            let sealed = seal::<Deterministic>("secret")?;
+            assert_eq!(open::<Deterministic>(sealed)?, "secret");
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn randomizes_randomized_values() -> Result<()> {
+        with_key_provider(provider(key()), || {
+            let first = seal::<Randomized>("secret")?;
+            let second = seal::<Randomized>("secret")?;
+            assert_ne!(first, second);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn encrypts_deterministic_values_stably() -> Result<()> {
+        with_key_provider(provider(key()), || {
+            let first = seal::<Deterministic>("secret")?;
+            let second = seal::<Deterministic>("secret")?;
+            let other = seal::<Deterministic>("other")?;
+            assert_eq!(first, second);
+            assert_ne!(first, other);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn decrypts_with_previous_keys() -> Result<()> {
+        let previous = EncryptionKey::new("previous", [2; 32]);
+        let sealed = with_key_provider(provider(previous.clone()), || {
+            seal::<Randomized>("secret")
+        })?;
+        let keys = EncryptionKeys::new(key()).with_previous(previous);
+        let provider: Arc<dyn KeyProvider> = Arc::new(keys);
+        let opened =
+            with_key_provider(Some(provider), || open::<Randomized>(sealed))?;
+        assert_eq!(opened, "secret");
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_values_sealed_with_other_secrets() -> Result<()> {
+        let sealed = with_key_provider(provider(key()), || {
+            seal::<Randomized>("secret")
+        })?;
+        let forged = EncryptionKey::new("current", [3; 32]);
+        let opened =
+            with_key_provider(provider(forged), || open::<Randomized>(sealed));
+        assert!(opened.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn requires_a_key_provider() {
+        assert!(seal::<Randomized>("secret").is_err());
+    }
+
+    #[test]
+    fn seals_pending_values() -> Result<()> {
+        let value = Encrypted::<_, Deterministic>::new("secret".to_owned());
+        let pending = Bson::from(value);
+        assert!(is_pending(&pending));
+
+        let conditions = doc! { "name": { "$in": [pending] } };
+        let sealed = with_key_provider(provider(key()), || {
+            seal_pending_document(conditions)
+        })?;
+        let expected = with_key_provider(provider(key()), || {
+            seal::<Deterministic>("secret")
+        })?;
+        assert_eq!(sealed, doc! { "name": { "$in": [expected] } });
+        check_sealed(&sealed)?;
+        Ok(())
+    }
+
+    #[test]
+    fn fails_to_seal_pending_values_without_a_key_provider() {
+        let value = Encrypted::<_, Deterministic>::new("secret".to_owned());
+        let conditions = doc! { "name": value };
+        assert!(seal_pending_document(conditions).is_err());
+    }
+
+    #[test]
+    fn rejects_pending_values_in_documents() {
+        let value = Encrypted::<_, Deterministic>::new("secret".to_owned());
+        let doc = doc! { "profile": { "names": [value] } };
+        assert!(check_sealed(&doc).is_err());
+    }
+}
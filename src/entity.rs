@@ -1,13 +1,21 @@
 use super::*;
 
 use mongodb::options::AggregateOptions;
+use mongodb::options::Collation;
 use mongodb::options::CountOptions;
+use mongodb::options::DistinctOptions;
+use mongodb::options::FindOneAndDeleteOptions;
+use mongodb::options::FindOneAndUpdateOptions;
 use mongodb::options::FindOneOptions;
 use mongodb::options::FindOptions;
 use mongodb::options::ReplaceOptions;
 
 use mongodb::error::Result as DatabaseResult;
-use mongodb::SessionCursor;
+
+use futures_util::future::ready;
+use futures_util::TryStreamExt;
+
+use std::collections::{HashMap, HashSet};
 
 use heck::MixedCase;
 
@@ -20,12 +28,18 @@ where
 {
     const NAME: &'static str;
 
+    type Key: EntityKey;
     type Services: EntityServices;
     type Conditions: EntityConditions;
     type Sorting: EntitySorting;
 
     fn id(&self) -> EntityId<Self>;
 
+    /// How ids of this entity are encoded as strings.
+    fn id_codec() -> IdCodec {
+        IdCodec::Global
+    }
+
     fn collection_name() -> String {
         Self::NAME.to_mixed_case()
     }
@@ -35,6 +49,89 @@ where
         ctx.database().collection(&name)
     }
 
+    /// Conditions that every query for this entity is restricted to, unless
+    /// the query is made `unscoped`.
+    fn default_scope() -> Option<Document> {
+        None
+    }
+
+    /// The entities that reference this one, and what happens to them when
+    /// it is deleted (or discarded, if [`Discardable`]).
+    ///
+    /// Dependents are only handled by the callback-running variants of
+    /// deletes and discards.
+    fn dependents() -> Vec<Dependent<Self>> {
+        Vec::new()
+    }
+
+    /// Enables recording changes to this entity in the audit log; see
+    /// [`Audited`].
+    fn auditor() -> Option<Auditor<Self>> {
+        None
+    }
+
+    /// Enables soft deletes for this entity; see [`Discardable`].
+    ///
+    /// Discarded entities are then left out of every query (along with those
+    /// outside the default scope), unless the query is made `unscoped`.
+    fn discarder() -> Option<Discarder<Self>> {
+        None
+    }
+
+    /// Conditions restricting the entities visible through `ctx` (i.e. to
+    /// those its actor may read).
+    ///
+    /// Unlike the default scope, these also apply to `unscoped` queries.
+    #[allow(unused_variables)]
+    fn read_filter(ctx: &EntityContext<Self::Services>) -> Option<Document> {
+        None
+    }
+
+    /// Whether this entity is visible through `ctx`. Entities that aren't are
+    /// left out of query results, as if they didn't exist.
+    #[allow(unused_variables)]
+    fn can_read(&self, ctx: &EntityContext<Self::Services>) -> bool {
+        true
+    }
+
+    /// Whether this entity may be saved or deleted through `ctx`. Writes that
+    /// aren't allowed fail with a [`ForbiddenError`].
+    #[allow(unused_variables)]
+    fn can_write(
+        &self,
+        ctx: &EntityContext<Self::Services>,
+        action: WriteAction,
+    ) -> bool {
+        true
+    }
+
+    /// Whether [`Entity::read_filter`] alone decides which entities are
+    /// visible (i.e. [`Entity::can_read`] agrees with it), so that selecting
+    /// objects (see [`FindQuery::select`]) can load only their fields.
+    ///
+    /// Entities that override `can_read` with checks that the read filter
+    /// doesn't express should return `false`, so that `can_read` is checked
+    /// on the client instead: objects are selected from full entities, and
+    /// queries are paged and counted over the entities that pass it, which
+    /// means loading every matching entity rather than letting the server
+    /// skip, take and count them.
+    fn read_filter_is_exact() -> bool {
+        true
+    }
+
+    /// Conditions restricting the entities that may be written through `ctx`
+    /// by writes that don't load entities beforehand (i.e.
+    /// [`FindQuery::delete_all_without_callbacks`], or
+    /// [`FindOneAndUpdateQuery`]), which can't check [`Entity::can_write`]
+    /// first; these should agree with it.
+    #[allow(unused_variables)]
+    fn write_filter(
+        ctx: &EntityContext<Self::Services>,
+        action: WriteAction,
+    ) -> Option<Document> {
+        None
+    }
+
     fn get(id: EntityId<Self>) -> FindOneQuery<Self> {
         FindOneQuery::new_untyped(doc! { "_id": id })
     }
@@ -76,19 +173,34 @@ where
         AggregateOneQuery::new(pipeline)
     }
 
-    async fn count(ctx: &EntityContext<Self::Services>) -> Result<u64> {
-        let collection = Self::collection(ctx);
-        let count = collection.estimated_document_count(None).await?;
-        Ok(count)
+    fn watch(
+        conditions: impl Into<Option<Self::Conditions>>,
+    ) -> WatchQuery<Self> {
+        WatchQuery::new(conditions)
+    }
+
+    async fn count(
+        ctx: &EntityContext<Self::Services>,
+        mode: CountMode,
+    ) -> Result<u64> {
+        if mode == CountMode::Estimated {
+            if let Some(count) = estimate_documents::<Self>(ctx).await? {
+                return Ok(count);
+            }
+        }
+        Self::all().count(ctx).await
     }
 
     async fn save(
         &mut self,
         ctx: &EntityContext<Self::Services>,
     ) -> Result<()> {
+        authorize_write(ctx, &*self, WriteAction::Save)?;
         self.validate().context("validation failed")?;
-        let replacement =
-            self.to_document().context("failed to serialize record")?;
+        let replacement = ctx
+            .with_encryption(|| self.to_document())
+            .context("failed to serialize record")?;
+        check_sealed(&replacement)?;
         ctx.with_transaction(|ctx, transaction| async move {
             let collection = Self::collection(&ctx);
             let id = self.id();
@@ -96,10 +208,18 @@ where
             let options = ReplaceOptions::builder().upsert(true).build();
 
             let mut transaction = transaction.lock().await;
+            transaction.track(&collection, &id).await?;
+            let auditor = Self::auditor();
+            let prior =
+                load_prior(&collection, &id, &mut transaction.session).await?;
+            if let Some(prior) = &prior {
+                authorize_prior::<Self>(&ctx, prior, WriteAction::Save)?;
+            }
             let Transaction {
                 session,
                 commit_finalizers,
                 abort_finalizers,
+                ..
             } = &mut *transaction;
             {
                 let entity = self.clone();
@@ -126,11 +246,19 @@ where
             collection
                 .replace_one_with_session(
                     conditions,
-                    replacement,
+                    &replacement,
                     options,
                     session,
                 )
                 .await?;
+            if let Some(auditor) = &auditor {
+                let (before, after) = (prior.as_ref(), Some(&replacement));
+                let operation = auditor.classify(before, after);
+                let transaction = &mut *transaction;
+                auditor
+                    .record(&ctx, transaction, id, operation, before, after)
+                    .await?;
+            }
             self.after_save(&ctx).await?;
             Ok(())
         })
@@ -141,9 +269,12 @@ where
         &self,
         ctx: &EntityContext<Self::Services>,
     ) -> Result<()> {
+        authorize_write(ctx, &*self, WriteAction::Save)?;
         self.validate().context("validation failed")?;
-        let replacement =
-            self.to_document().context("failed to serialize record")?;
+        let replacement = ctx
+            .with_encryption(|| self.to_document())
+            .context("failed to serialize record")?;
+        check_sealed(&replacement)?;
         ctx.with_transaction(|ctx, transaction| async move {
             let collection = Self::collection(&ctx);
             let id = self.id();
@@ -151,6 +282,13 @@ where
             let options = ReplaceOptions::builder().upsert(true).build();
 
             let mut transaction = transaction.lock().await;
+            transaction.track(&collection, &id).await?;
+            let auditor = Self::auditor();
+            let prior =
+                load_prior(&collection, &id, &mut transaction.session).await?;
+            if let Some(prior) = &prior {
+                authorize_prior::<Self>(&ctx, prior, WriteAction::Save)?;
+            }
             let Transaction { session, .. } = &mut *transaction;
 
             trace!(
@@ -162,30 +300,74 @@ where
             collection
                 .replace_one_with_session(
                     conditions,
-                    replacement,
+                    &replacement,
                     options,
                     session,
                 )
                 .await?;
+            if let Some(auditor) = &auditor {
+                let (before, after) = (prior.as_ref(), Some(&replacement));
+                let operation = auditor.classify(before, after);
+                let transaction = &mut *transaction;
+                auditor
+                    .record(&ctx, transaction, id, operation, before, after)
+                    .await?;
+            }
             Ok(())
         })
         .await
     }
 
+    /// Saves `entities` in batches, running validation and callbacks for each
+    /// of them.
+    ///
+    /// Entities that may not be written, or fail validation, serialization
+    /// or `before_save`, are skipped and reported in the returned
+    /// [`BulkWriteReport`]. Entities rejected by the database (i.e. because
+    /// of a duplicate key) abort the transaction, failing the whole write
+    /// with a [`BulkWriteError`] that reports each of them; any other
+    /// failure fails the whole write as well.
+    async fn save_many(
+        ctx: &EntityContext<Self::Services>,
+        entities: &mut [Self],
+    ) -> Result<BulkWriteReport> {
+        write_many(ctx, entities, BulkWriteMode::Upsert).await
+    }
+
+    /// Like [`Entity::save_many`], but inserts `entities`, failing if any of
+    /// them already exist.
+    async fn insert_many(
+        ctx: &EntityContext<Self::Services>,
+        entities: &mut [Self],
+    ) -> Result<BulkWriteReport> {
+        write_many(ctx, entities, BulkWriteMode::Insert).await
+    }
+
     async fn delete(
         &mut self,
         ctx: &EntityContext<Self::Services>,
     ) -> Result<()> {
+        authorize_write(ctx, &*self, WriteAction::Delete)?;
         ctx.with_transaction(|ctx, transaction| async move {
             let collection = Self::collection(&ctx);
             let id = self.id();
             let conditions = doc! { "_id": &id };
+            self.before_delete(&ctx).await?;
+            handle_dependents(&ctx, &*self, DependentEvent::Delete).await?;
 
             let mut transaction = transaction.lock().await;
+            transaction.track(&collection, &id).await?;
+            let auditor = Self::auditor();
+            let prior =
+                load_prior(&collection, &id, &mut transaction.session).await?;
+            if let Some(prior) = &prior {
+                authorize_prior::<Self>(&ctx, prior, WriteAction::Delete)?;
+            }
             let Transaction {
                 session,
                 commit_finalizers,
                 abort_finalizers,
+                ..
             } = &mut *transaction;
             {
                 let entity = self.clone();
@@ -202,7 +384,6 @@ where
                 abort_finalizers.push(finalizer.boxed());
             }
 
-            self.before_delete(&ctx).await?;
             trace!(
                 collection = collection.name(),
                 %id,
@@ -210,8 +391,16 @@ where
                 "deleting document"
             );
             collection
-                .delete_one_with_session(doc! { "_id": id }, None, session)
+                .delete_one_with_session(doc! { "_id": &id }, None, session)
                 .await?;
+            if let Some(auditor) = &auditor {
+                let before = prior.as_ref();
+                let operation = AuditOperation::Delete;
+                let transaction = &mut *transaction;
+                auditor
+                    .record(&ctx, transaction, id, operation, before, None)
+                    .await?;
+            }
             self.after_delete(&ctx).await?;
             Ok(())
         })
@@ -222,12 +411,20 @@ where
         &mut self,
         ctx: &EntityContext<Self::Services>,
     ) -> Result<()> {
+        authorize_write(ctx, &*self, WriteAction::Delete)?;
         ctx.with_transaction(|ctx, transaction| async move {
             let collection = Self::collection(&ctx);
             let id = self.id();
             let conditions = doc! { "_id": &id };
 
             let mut transaction = transaction.lock().await;
+            transaction.track(&collection, &id).await?;
+            let auditor = Self::auditor();
+            let prior =
+                load_prior(&collection, &id, &mut transaction.session).await?;
+            if let Some(prior) = &prior {
+                authorize_prior::<Self>(&ctx, prior, WriteAction::Delete)?;
+            }
             let Transaction { session, .. } = &mut *transaction;
 
             trace!(
@@ -237,13 +434,40 @@ where
                 "deleting document"
             );
             collection
-                .delete_one_with_session(doc! { "_id": id }, None, session)
+                .delete_one_with_session(doc! { "_id": &id }, None, session)
                 .await?;
+            if let Some(auditor) = &auditor {
+                let before = prior.as_ref();
+                let operation = AuditOperation::Delete;
+                let transaction = &mut *transaction;
+                auditor
+                    .record(&ctx, transaction, id, operation, before, None)
+                    .await?;
+            }
             Ok(())
         })
         .await
     }
 
+    async fn delete_many(
+        ctx: &EntityContext<Self::Services>,
+        conditions: impl Into<Option<Self::Conditions>> + Send + 'static,
+    ) -> Result<u64> {
+        Self::find(conditions).delete_all(ctx).await
+    }
+
+    /// Deletes the matching entities with
+    /// [`FindQuery::delete_all_without_callbacks`], which only deletes those
+    /// matching [`Entity::write_filter`].
+    async fn delete_many_without_callbacks(
+        ctx: &EntityContext<Self::Services>,
+        conditions: impl Into<Option<Self::Conditions>> + Send + 'static,
+    ) -> Result<u64> {
+        Self::find(conditions)
+            .delete_all_without_callbacks(ctx)
+            .await
+    }
+
     fn validate(&self) -> Result<()> {
         Ok(())
     }
@@ -332,9 +556,51 @@ impl<T: Entity> FindOneQuery<T> {
         MaybeFindOneQuery(inner)
     }
 
+    pub fn unscoped(self) -> Self {
+        let Self(inner) = self;
+        Self(inner.unscoped())
+    }
+
+    /// Includes discarded entities, bypassing only the [`Entity::discarder`]
+    /// part of the default scope.
+    pub fn with_discarded(self) -> Self {
+        let Self(inner) = self;
+        Self(inner.with_discarded())
+    }
+
+    pub(super) fn and_untyped(
+        self,
+        conditions: impl Into<Option<Document>>,
+    ) -> Self {
+        let Self(inner) = self;
+        Self(inner.and_untyped(conditions))
+    }
+
+    pub fn and_update(self, update: Document) -> FindOneAndUpdateQuery<T> {
+        let Self(inner) = self;
+        let inner = FindOneAndUpdateQueryInner::new(inner, update);
+        FindOneAndUpdateQuery(inner)
+    }
+
+    pub fn and_delete(self) -> FindOneAndDeleteQuery<T> {
+        let Self(inner) = self;
+        let inner = FindOneAndDeleteQueryInner::new(inner);
+        FindOneAndDeleteQuery(inner)
+    }
+
+    /// Loads the entity as `P`; see [`FindQuery::select`].
+    pub fn select<P>(self) -> SelectOneQuery<T, P>
+    where
+        P: Object + DeserializeOwned,
+    {
+        let Self(inner) = self;
+        SelectOneQuery(SelectOneQueryInner::new(inner))
+    }
+
     pub async fn load(self, ctx: &EntityContext<T::Services>) -> Result<T> {
         let Self(inner) = self;
-        inner.load(ctx).await?.context("not found")
+        let entity = inner.load(ctx).await?;
+        entity.ok_or_else(|| NotFoundError::new::<T>().into())
     }
 
     pub async fn exists(
@@ -360,6 +626,47 @@ impl<T: Entity> MaybeFindOneQuery<T> {
         FindOneQuery(inner)
     }
 
+    pub fn unscoped(self) -> Self {
+        let Self(inner) = self;
+        Self(inner.unscoped())
+    }
+
+    /// Includes discarded entities, bypassing only the [`Entity::discarder`]
+    /// part of the default scope.
+    pub fn with_discarded(self) -> Self {
+        let Self(inner) = self;
+        Self(inner.with_discarded())
+    }
+
+    pub(super) fn and_untyped(
+        self,
+        conditions: impl Into<Option<Document>>,
+    ) -> Self {
+        let Self(inner) = self;
+        Self(inner.and_untyped(conditions))
+    }
+
+    pub fn and_update(self, update: Document) -> MaybeFindOneAndUpdateQuery<T> {
+        let Self(inner) = self;
+        let inner = FindOneAndUpdateQueryInner::new(inner, update);
+        MaybeFindOneAndUpdateQuery(inner)
+    }
+
+    pub fn and_delete(self) -> MaybeFindOneAndDeleteQuery<T> {
+        let Self(inner) = self;
+        let inner = FindOneAndDeleteQueryInner::new(inner);
+        MaybeFindOneAndDeleteQuery(inner)
+    }
+
+    /// Loads the entity as `P`; see [`FindQuery::select`].
+    pub fn select<P>(self) -> MaybeSelectOneQuery<T, P>
+    where
+        P: Object + DeserializeOwned,
+    {
+        let Self(inner) = self;
+        MaybeSelectOneQuery(SelectOneQueryInner::new(inner))
+    }
+
     pub async fn load(
         self,
         ctx: &EntityContext<T::Services>,
@@ -381,6 +688,8 @@ impl<T: Entity> MaybeFindOneQuery<T> {
 struct FindOneQueryInner<T: Entity> {
     conditions: Option<Document>,
     options: FindOneOptions,
+    scoped: bool,
+    kept: bool,
     phantom: PhantomData<T>,
 }
 
@@ -396,24 +705,94 @@ impl<T: Entity> FindOneQueryInner<T> {
         Self {
             conditions: conditions.into(),
             options: default(),
+            scoped: true,
+            kept: true,
             phantom: default(),
         }
     }
 
+    pub fn unscoped(mut self) -> Self {
+        self.scoped = false;
+        self
+    }
+
+    pub fn with_discarded(mut self) -> Self {
+        self.kept = false;
+        self
+    }
+
+    pub fn and_untyped(
+        mut self,
+        conditions: impl Into<Option<Document>>,
+    ) -> Self {
+        self.conditions = and_conditions(self.conditions, conditions.into());
+        self
+    }
+
     pub async fn load(
         self,
         ctx: &EntityContext<T::Services>,
     ) -> Result<Option<T>> {
+        if !T::read_filter_is_exact() {
+            let entities = self.into_find_query().load(ctx).await?;
+            pin_mut!(entities);
+            return entities.next().await.transpose();
+        }
+        let doc = match self.find_document(ctx).await? {
+            Some(doc) => doc,
+            None => return Ok(None),
+        };
+        let object = ctx.with_encryption(|| T::from_document(doc))?;
+        if !object.can_read(ctx) {
+            return Ok(None);
+        }
+        Ok(Some(object))
+    }
+
+    /// A query for all of the matching entities, to look for the first one
+    /// that passes [`Entity::can_read`] on the client.
+    fn into_find_query(self) -> FindQuery<T> {
+        let Self {
+            conditions,
+            options,
+            scoped,
+            kept,
+            ..
+        } = self;
+        let FindOneOptions {
+            collation,
+            skip,
+            sort,
+            ..
+        } = options;
+        let mut query = FindQuery::new_untyped(conditions);
+        query.options.collation = collation;
+        query.options.skip = skip;
+        if sort.is_some() {
+            query.options.sort = sort;
+        }
+        query.scoped = scoped;
+        query.kept = kept;
+        query
+    }
+
+    async fn find_document(
+        self,
+        ctx: &EntityContext<T::Services>,
+    ) -> Result<Option<Document>> {
         let Self {
             conditions,
             options,
+            scoped,
+            kept,
             ..
         } = self;
+        let conditions =
+            query_conditions::<T>(ctx, conditions, scoped, kept, true)?;
         let collection = T::collection(ctx);
 
-        let doc = if let Some(transaction) = &ctx.transaction {
-            let mut transaction = transaction.lock().await;
-            let session = &mut transaction.session;
+        let doc = if let Some(session) = ctx.session().await? {
+            let mut session = session.lock().await;
             if let Some(conditions) = &conditions {
                 trace!(
                     collection = collection.name(),
@@ -431,7 +810,7 @@ impl<T: Entity> FindOneQueryInner<T> {
                 );
             }
             collection
-                .find_one_with_session(conditions, options, session)
+                .find_one_with_session(conditions, options, &mut session)
                 .await?
         } else {
             if let Some(conditions) = &conditions {
@@ -450,166 +829,861 @@ impl<T: Entity> FindOneQueryInner<T> {
             }
             collection.find_one(conditions, options).await?
         };
-
-        let doc = match doc {
-            Some(doc) => doc,
-            None => return Ok(None),
-        };
-        let object = T::from_document(doc)?;
-        Ok(Some(object))
+        Ok(doc)
     }
 
     pub async fn exists(
         self,
         ctx: &EntityContext<T::Services>,
     ) -> Result<bool> {
-        let Self { conditions, .. } = self;
-        let collection = T::collection(ctx);
-        let count = collection.count_documents(conditions, None).await?;
+        if !T::read_filter_is_exact() {
+            let entity = self.load(ctx).await?;
+            return Ok(entity.is_some());
+        }
+        let Self {
+            conditions,
+            scoped,
+            kept,
+            ..
+        } = self;
+        let conditions =
+            query_conditions::<T>(ctx, conditions, scoped, kept, true)?;
+        let options = CountOptions::builder().limit(1).build();
+        let count = count_documents::<T>(ctx, conditions, options).await?;
         Ok(count > 0)
     }
 }
 
-pub struct FindQuery<T: Entity> {
-    conditions: Option<Document>,
-    options: FindOptions,
-    phantom: PhantomData<T>,
-}
+#[derive(Derivative)]
+#[derivative(Debug(bound = "T: Debug"), Clone(bound = ""))]
+pub struct SelectOneQuery<T: Entity, P: Object>(SelectOneQueryInner<T, P>);
 
-fn document_has_operator(doc: &Document, operator: &str) -> bool {
-    for (key, value) in doc {
-        if key.starts_with('$') {
-            if key == operator {
-                return true;
-            }
-            if dbg!(document_value_has_operator(value, operator)) {
-                return true;
-            }
-        }
+impl<T: Entity, P: Object + DeserializeOwned> SelectOneQuery<T, P> {
+    pub fn optional(self) -> MaybeSelectOneQuery<T, P> {
+        let Self(inner) = self;
+        MaybeSelectOneQuery(inner)
     }
-    false
-}
 
-fn document_value_has_operator(value: &Bson, operator: &str) -> bool {
-    use Bson::*;
-    match value {
-        Document(doc) => dbg!(document_has_operator(doc, operator)),
-        Array(array) => dbg!(document_array_has_operator(array, operator)),
-        _ => false,
+    pub async fn load(self, ctx: &EntityContext<T::Services>) -> Result<P> {
+        let Self(inner) = self;
+        let object = inner.load(ctx).await?;
+        object.ok_or_else(|| NotFoundError::new::<T>().into())
     }
 }
 
-fn document_array_has_operator(array: &[Bson], operator: &str) -> bool {
-    for entry in array {
-        if document_value_has_operator(entry, operator) {
-            return true;
-        }
+#[derive(Derivative)]
+#[derivative(Debug(bound = "T: Debug"), Clone(bound = ""))]
+pub struct MaybeSelectOneQuery<T: Entity, P: Object>(SelectOneQueryInner<T, P>);
+
+impl<T: Entity, P: Object + DeserializeOwned> MaybeSelectOneQuery<T, P> {
+    pub fn required(self) -> SelectOneQuery<T, P> {
+        let Self(inner) = self;
+        SelectOneQuery(inner)
     }
-    false
-}
 
-impl<T: Entity> FindQuery<T> {
-    pub fn new(conditions: impl Into<Option<T::Conditions>>) -> Self {
-        Self::new_untyped({
-            let conditions: Option<_> = conditions.into();
-            conditions.as_ref().map(EntityConditions::to_document)
-        })
+    pub async fn load(
+        self,
+        ctx: &EntityContext<T::Services>,
+    ) -> Result<Option<P>> {
+        let Self(inner) = self;
+        inner.load(ctx).await
     }
+}
 
-    pub(super) fn new_untyped(conditions: impl Into<Option<Document>>) -> Self {
-        let conditions: Option<_> = conditions.into();
-        let options = {
-            let mut options = FindOptions::default();
-            if let Some(conditions) = &conditions {
-                if document_has_operator(conditions, "$text") {
-                    let sort = doc! { "score": { "$meta": "textScore" } };
-                    options.sort = Some(sort);
-                }
-            }
-            options
-        };
+#[derive(Derivative)]
+#[derivative(Debug(bound = "T: Debug"), Clone(bound = ""))]
+struct SelectOneQueryInner<T: Entity, P: Object> {
+    query: FindOneQueryInner<T>,
+    phantom: PhantomData<P>,
+}
+
+impl<T: Entity, P: Object + DeserializeOwned> SelectOneQueryInner<T, P> {
+    fn new(query: FindOneQueryInner<T>) -> Self {
         Self {
-            conditions,
-            options,
+            query,
             phantom: default(),
         }
     }
 
-    pub fn and(mut self, conditions: impl Into<Option<T::Conditions>>) -> Self {
-        let incoming: Option<Document> = {
-            let conditions: Option<_> = conditions.into();
-            conditions.as_ref().map(EntityConditions::to_document)
-        };
-        if let Some(incoming) = incoming {
-            let conditions = match self.conditions {
-                Some(existing) => {
-                    doc! {
-                        "$and": [existing, incoming],
-                    }
-                }
-                None => incoming,
-            };
-            self.conditions = Some(conditions);
+    async fn load(self, ctx: &EntityContext<T::Services>) -> Result<Option<P>> {
+        let Self { mut query, .. } = self;
+        if !T::read_filter_is_exact() {
+            let objects =
+                query.into_find_query().select::<P>().load(ctx).await?;
+            pin_mut!(objects);
+            return objects.next().await.transpose();
         }
-        self
+        query.options.projection = P::projection();
+        let doc = match query.find_document(ctx).await? {
+            Some(doc) => doc,
+            None => return Ok(None),
+        };
+        let object = ctx
+            .with_encryption(|| P::from_document(doc))
+            .context("failed to deserialize object")?;
+        Ok(Some(object))
     }
+}
 
-    pub fn skip(mut self, n: impl Into<Option<u64>>) -> Self {
-        self.options.skip = n.into();
-        self
-    }
+/// Atomically updates the first matching document, returning the entity as
+/// it was before the update (or after, see
+/// [`FindOneAndUpdateQuery::returning`]).
+///
+/// No callbacks are run, and skipping documents isn't supported. Only the
+/// entities visible through the context, and that pass
+/// [`Entity::write_filter`], are updated.
+///
+/// Updates of audited entities, or made within a transaction, are checked
+/// against [`Entity::can_write`] as well: they fail with a [`ForbiddenError`]
+/// (and are undone) unless the entity may be saved both before and after
+/// them. Other updates are made in a single atomic operation.
+#[derive(Debug, Clone)]
+pub struct FindOneAndUpdateQuery<T: Entity>(FindOneAndUpdateQueryInner<T>);
 
-    pub fn take(mut self, n: impl Into<Option<u64>>) -> Self {
-        let n: Option<u64> = n.into();
-        self.options.limit = n.map(|n| {
-            i64::try_from(n).unwrap_or_else(|_| {
-                warn!(
-                    take = n,
-                    "take option has overflowing value; using i64::MAX instead"
-                );
-                i64::MAX
-            })
-        });
-        self
+impl<T: Entity> FindOneAndUpdateQuery<T> {
+    pub fn optional(self) -> MaybeFindOneAndUpdateQuery<T> {
+        let Self(inner) = self;
+        MaybeFindOneAndUpdateQuery(inner)
     }
 
-    pub fn sort(mut self, sorting: impl Into<Option<T::Sorting>>) -> Self {
-        let existing = self.options.sort.take();
-        let incoming: Option<_> = sorting.into();
-        self.options.sort = match incoming {
-            Some(incoming) => {
-                let incoming = incoming.to_document();
-                let combined = match existing {
-                    Some(mut existing) => {
-                        existing.extend(incoming);
-                        existing
-                    }
-                    None => incoming,
-                };
-                Some(combined)
-            }
-            None => existing,
-        };
-        self
+    pub fn returning(self, document: ReturnDocument) -> Self {
+        let Self(inner) = self;
+        Self(inner.returning(document))
     }
 
-    pub async fn load<'a>(
+    pub fn upsert(self, upsert: impl Into<Option<bool>>) -> Self {
+        let Self(inner) = self;
+        Self(inner.upsert(upsert))
+    }
+
+    pub async fn load(self, ctx: &EntityContext<T::Services>) -> Result<T> {
+        let Self(inner) = self;
+        let entity = inner.load(ctx).await?;
+        entity.ok_or_else(|| NotFoundError::new::<T>().into())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MaybeFindOneAndUpdateQuery<T: Entity>(FindOneAndUpdateQueryInner<T>);
+
+impl<T: Entity> MaybeFindOneAndUpdateQuery<T> {
+    pub fn required(self) -> FindOneAndUpdateQuery<T> {
+        let Self(inner) = self;
+        FindOneAndUpdateQuery(inner)
+    }
+
+    pub fn returning(self, document: ReturnDocument) -> Self {
+        let Self(inner) = self;
+        Self(inner.returning(document))
+    }
+
+    pub fn upsert(self, upsert: impl Into<Option<bool>>) -> Self {
+        let Self(inner) = self;
+        Self(inner.upsert(upsert))
+    }
+
+    pub async fn load(
+        self,
+        ctx: &EntityContext<T::Services>,
+    ) -> Result<Option<T>> {
+        let Self(inner) = self;
+        inner.load(ctx).await
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FindOneAndUpdateQueryInner<T: Entity> {
+    conditions: Option<Document>,
+    scoped: bool,
+    kept: bool,
+    update: Document,
+    options: FindOneAndUpdateOptions,
+    skip: Option<u64>,
+    phantom: PhantomData<T>,
+}
+
+impl<T: Entity> FindOneAndUpdateQueryInner<T> {
+    fn new(query: FindOneQueryInner<T>, update: Document) -> Self {
+        let FindOneQueryInner {
+            conditions,
+            options,
+            scoped,
+            kept,
+            ..
+        } = query;
+        let FindOneOptions {
+            collation,
+            projection,
+            skip,
+            sort,
+            ..
+        } = options;
+        let options = FindOneAndUpdateOptions::builder()
+            .collation(collation)
+            .projection(projection)
+            .sort(sort)
+            .build();
+        Self {
+            conditions,
+            scoped,
+            kept,
+            update,
+            options,
+            skip,
+            phantom: default(),
+        }
+    }
+
+    fn returning(mut self, document: ReturnDocument) -> Self {
+        self.options.return_document = Some(document);
+        self
+    }
+
+    fn upsert(mut self, upsert: impl Into<Option<bool>>) -> Self {
+        self.options.upsert = upsert.into();
+        self
+    }
+
+    async fn load(self, ctx: &EntityContext<T::Services>) -> Result<Option<T>> {
+        let Self {
+            conditions,
+            scoped,
+            kept,
+            update,
+            options,
+            skip,
+            ..
+        } = self;
+        if skip.is_some() {
+            bail!("find-and-modify queries can't skip documents");
+        }
+        let update = seal_conditions(ctx, update)?;
+        let conditions = {
+            let filter = T::write_filter(ctx, WriteAction::Save);
+            let conditions = and_conditions(conditions, filter);
+            query_conditions::<T>(ctx, conditions, scoped, kept, true)?
+                .unwrap_or_default()
+        };
+        let collection = T::collection(ctx);
+
+        // Unless the update has to be audited or tracked by a savepoint, it
+        // is made in a single atomic operation.
+        if T::auditor().is_none() && ctx.transaction.is_none() {
+            trace!(
+                collection = collection.name(),
+                %conditions,
+                %update,
+                "finding and updating document"
+            );
+            let doc = if let Some(session) = ctx.session().await? {
+                let mut session = session.lock().await;
+                collection
+                    .find_one_and_update_with_session(
+                        conditions,
+                        update,
+                        options,
+                        &mut session,
+                    )
+                    .await?
+            } else {
+                collection
+                    .find_one_and_update(conditions, update, options)
+                    .await?
+            };
+            let entity = doc
+                .map(|doc| ctx.with_encryption(|| T::from_document(doc)))
+                .transpose()
+                .context("failed to deserialize entity")?;
+            return Ok(entity.filter(|entity| entity.can_read(ctx)));
+        }
+
+        ctx.with_retried_transaction(|ctx, transaction| {
+            let collection = collection.clone();
+            let conditions = conditions.clone();
+            let update = update.clone();
+            let mut options = options.clone();
+            async move {
+                let mut transaction = transaction.lock().await;
+
+                // The prior state is returned by the update itself, and the
+                // updated state is then read back within the transaction.
+                let return_document = options
+                    .return_document
+                    .replace(ReturnDocument::Before)
+                    .unwrap_or(ReturnDocument::Before);
+                let upsert = options.upsert.unwrap_or_default();
+                let (sort, collation) =
+                    (options.sort.clone(), options.collation.clone());
+                trace!(
+                    collection = collection.name(),
+                    %conditions,
+                    %update,
+                    "finding and updating document"
+                );
+                let prior = collection
+                    .find_one_and_update_with_session(
+                        conditions.clone(),
+                        update,
+                        options,
+                        &mut transaction.session,
+                    )
+                    .await?;
+                let doc = match &prior {
+                    Some(prior) => {
+                        let id =
+                            prior.get("_id").context("missing document id")?;
+                        transaction.track_prior(
+                            &collection,
+                            id.clone(),
+                            prior.clone(),
+                        );
+                        let session = &mut transaction.session;
+                        load_prior(&collection, id.clone(), session).await?
+                    }
+                    // Nothing matched, so the only document that matches
+                    // now is the upserted one.
+                    None if upsert => {
+                        let doc = find_target(
+                            &collection,
+                            conditions,
+                            sort,
+                            collation,
+                            &mut transaction.session,
+                        )
+                        .await?
+                        .context("failed to find upserted document")?;
+                        let id =
+                            doc.get("_id").context("missing document id")?;
+                        transaction.track_inserted(&collection, id.clone());
+                        Some(doc)
+                    }
+                    None => None,
+                };
+
+                // Failing here aborts the transaction, undoing the update.
+                let prior_entity = prior
+                    .as_ref()
+                    .map(|doc| {
+                        ctx.with_encryption(|| T::from_document(doc.to_owned()))
+                    })
+                    .transpose()
+                    .context("failed to deserialize entity")?;
+                if let Some(entity) = &prior_entity {
+                    let action = WriteAction::Save;
+                    if !entity.can_read(&ctx) || !entity.can_write(&ctx, action)
+                    {
+                        let error = ForbiddenError::new(&ctx, entity, action);
+                        return Err(error.into());
+                    }
+                }
+                let entity = doc
+                    .as_ref()
+                    .map(|doc| {
+                        ctx.with_encryption(|| T::from_document(doc.to_owned()))
+                    })
+                    .transpose()
+                    .context("failed to deserialize entity")?;
+                if let Some(entity) = &entity {
+                    authorize_write(&ctx, entity, WriteAction::Save)?;
+                }
+
+                let (before, after) = (prior.as_ref(), doc.as_ref());
+                if let (Some(auditor), Some(after)) = (T::auditor(), after) {
+                    let id = after.get("_id").context("missing document id")?;
+                    let id = EntityId::from_bson(id)?;
+                    let operation = auditor.classify(before, Some(after));
+                    let transaction = &mut *transaction;
+                    auditor
+                        .record(
+                            &ctx,
+                            transaction,
+                            id,
+                            operation,
+                            before,
+                            Some(after),
+                        )
+                        .await?;
+                }
+
+                let entity = match return_document {
+                    ReturnDocument::After => entity,
+                    _ => prior_entity,
+                };
+                Ok(entity.filter(|entity| entity.can_read(&ctx)))
+            }
+        })
+        .await
+    }
+}
+
+/// Atomically deletes the first matching document, returning the deleted
+/// entity.
+///
+/// No callbacks are run, and skipping documents isn't supported. Only the
+/// entities visible through the context, and that pass
+/// [`Entity::write_filter`], are deleted.
+///
+/// Deletes of audited entities, or made within a transaction, are checked
+/// against [`Entity::can_write`] as well: they fail with a [`ForbiddenError`]
+/// (and are undone) unless the entity may be deleted. Other deletes are made
+/// in a single atomic operation.
+#[derive(Debug, Clone)]
+pub struct FindOneAndDeleteQuery<T: Entity>(FindOneAndDeleteQueryInner<T>);
+
+impl<T: Entity> FindOneAndDeleteQuery<T> {
+    pub fn optional(self) -> MaybeFindOneAndDeleteQuery<T> {
+        let Self(inner) = self;
+        MaybeFindOneAndDeleteQuery(inner)
+    }
+
+    pub async fn load(self, ctx: &EntityContext<T::Services>) -> Result<T> {
+        let Self(inner) = self;
+        let entity = inner.load(ctx).await?;
+        entity.ok_or_else(|| NotFoundError::new::<T>().into())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MaybeFindOneAndDeleteQuery<T: Entity>(FindOneAndDeleteQueryInner<T>);
+
+impl<T: Entity> MaybeFindOneAndDeleteQuery<T> {
+    pub fn required(self) -> FindOneAndDeleteQuery<T> {
+        let Self(inner) = self;
+        FindOneAndDeleteQuery(inner)
+    }
+
+    pub async fn load(
+        self,
+        ctx: &EntityContext<T::Services>,
+    ) -> Result<Option<T>> {
+        let Self(inner) = self;
+        inner.load(ctx).await
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FindOneAndDeleteQueryInner<T: Entity> {
+    conditions: Option<Document>,
+    scoped: bool,
+    kept: bool,
+    options: FindOneAndDeleteOptions,
+    skip: Option<u64>,
+    phantom: PhantomData<T>,
+}
+
+impl<T: Entity> FindOneAndDeleteQueryInner<T> {
+    fn new(query: FindOneQueryInner<T>) -> Self {
+        let FindOneQueryInner {
+            conditions,
+            options,
+            scoped,
+            kept,
+            ..
+        } = query;
+        let FindOneOptions {
+            collation,
+            projection,
+            skip,
+            sort,
+            ..
+        } = options;
+        let options = FindOneAndDeleteOptions::builder()
+            .collation(collation)
+            .projection(projection)
+            .sort(sort)
+            .build();
+        Self {
+            conditions,
+            scoped,
+            kept,
+            options,
+            skip,
+            phantom: default(),
+        }
+    }
+
+    async fn load(self, ctx: &EntityContext<T::Services>) -> Result<Option<T>> {
+        let Self {
+            conditions,
+            scoped,
+            kept,
+            options,
+            skip,
+            ..
+        } = self;
+        if skip.is_some() {
+            bail!("find-and-modify queries can't skip documents");
+        }
+        let conditions = {
+            let filter = T::write_filter(ctx, WriteAction::Delete);
+            let conditions = and_conditions(conditions, filter);
+            query_conditions::<T>(ctx, conditions, scoped, kept, true)?
+                .unwrap_or_default()
+        };
+        let collection = T::collection(ctx);
+
+        // Unless the delete has to be audited or tracked by a savepoint, it
+        // is made in a single atomic operation.
+        if T::auditor().is_none() && ctx.transaction.is_none() {
+            trace!(
+                collection = collection.name(),
+                %conditions,
+                "finding and deleting document"
+            );
+            let doc = if let Some(session) = ctx.session().await? {
+                let mut session = session.lock().await;
+                collection
+                    .find_one_and_delete_with_session(
+                        conditions,
+                        options,
+                        &mut session,
+                    )
+                    .await?
+            } else {
+                collection.find_one_and_delete(conditions, options).await?
+            };
+            let entity = doc
+                .map(|doc| ctx.with_encryption(|| T::from_document(doc)))
+                .transpose()
+                .context("failed to deserialize entity")?;
+            return Ok(entity.filter(|entity| entity.can_read(ctx)));
+        }
+
+        ctx.with_retried_transaction(|ctx, transaction| {
+            let collection = collection.clone();
+            let conditions = conditions.clone();
+            let options = options.clone();
+            async move {
+                let mut transaction = transaction.lock().await;
+                trace!(
+                    collection = collection.name(),
+                    %conditions,
+                    "finding and deleting document"
+                );
+                let prior = collection
+                    .find_one_and_delete_with_session(
+                        conditions,
+                        options,
+                        &mut transaction.session,
+                    )
+                    .await?;
+                let prior = match prior {
+                    Some(prior) => prior,
+                    None => return Ok(None),
+                };
+                let id = prior.get("_id").context("missing document id")?;
+                transaction.track_prior(&collection, id.clone(), prior.clone());
+
+                // Failing here aborts the transaction, undoing the delete.
+                let entity = ctx
+                    .with_encryption(|| T::from_document(prior.clone()))
+                    .context("failed to deserialize entity")?;
+                let action = WriteAction::Delete;
+                if !entity.can_read(&ctx) || !entity.can_write(&ctx, action) {
+                    let error = ForbiddenError::new(&ctx, &entity, action);
+                    return Err(error.into());
+                }
+
+                if let Some(auditor) = T::auditor() {
+                    let id = EntityId::from_bson(id)?;
+                    let operation = AuditOperation::Delete;
+                    let transaction = &mut *transaction;
+                    auditor
+                        .record(
+                            &ctx,
+                            transaction,
+                            id,
+                            operation,
+                            Some(&prior),
+                            None,
+                        )
+                        .await?;
+                }
+                Ok(Some(entity))
+            }
+        })
+        .await
+    }
+}
+
+/// The number of entities deleted at a time by [`FindQuery::delete_all`].
+const DELETE_BATCH_SIZE: usize = 100;
+
+type DocumentCursor =
+    Box<dyn Stream<Item = DatabaseResult<Document>> + Send + Unpin>;
+
+pub struct FindQuery<T: Entity> {
+    conditions: Option<Document>,
+    options: FindOptions,
+    scoped: bool,
+    kept: bool,
+    filtered: bool,
+    phantom: PhantomData<T>,
+}
+
+fn document_has_operator(doc: &Document, operator: &str) -> bool {
+    for (key, value) in doc {
+        if key.starts_with('$') {
+            if key == operator {
+                return true;
+            }
+            if dbg!(document_value_has_operator(value, operator)) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn document_value_has_operator(value: &Bson, operator: &str) -> bool {
+    use Bson::*;
+    match value {
+        Document(doc) => dbg!(document_has_operator(doc, operator)),
+        Array(array) => dbg!(document_array_has_operator(array, operator)),
+        _ => false,
+    }
+}
+
+fn document_array_has_operator(array: &[Bson], operator: &str) -> bool {
+    for entry in array {
+        if document_value_has_operator(entry, operator) {
+            return true;
+        }
+    }
+    false
+}
+
+impl<T: Entity> FindQuery<T> {
+    pub fn new(conditions: impl Into<Option<T::Conditions>>) -> Self {
+        Self::new_untyped({
+            let conditions: Option<_> = conditions.into();
+            conditions.as_ref().map(EntityConditions::to_document)
+        })
+    }
+
+    pub(super) fn new_untyped(conditions: impl Into<Option<Document>>) -> Self {
+        let conditions: Option<_> = conditions.into();
+        let options = {
+            let mut options = FindOptions::default();
+            if let Some(conditions) = &conditions {
+                if document_has_operator(conditions, "$text") {
+                    let sort = doc! { "score": { "$meta": "textScore" } };
+                    options.sort = Some(sort);
+                }
+            }
+            options
+        };
+        Self {
+            conditions,
+            options,
+            scoped: true,
+            kept: true,
+            filtered: true,
+            phantom: default(),
+        }
+    }
+
+    pub fn unscoped(mut self) -> Self {
+        self.scoped = false;
+        self
+    }
+
+    /// Includes discarded entities, bypassing only the [`Entity::discarder`]
+    /// part of the default scope.
+    pub fn with_discarded(mut self) -> Self {
+        self.kept = false;
+        self
+    }
+
+    /// Bypasses the read policy of `T` (i.e. [`Entity::read_filter`] and
+    /// [`Entity::can_read`]), for integrity checks that must see every
+    /// entity regardless of the actor.
+    pub(super) fn unfiltered(mut self) -> Self {
+        self.filtered = false;
+        self
+    }
+
+    pub fn and(self, conditions: impl Into<Option<T::Conditions>>) -> Self {
+        self.and_untyped({
+            let conditions: Option<_> = conditions.into();
+            conditions.as_ref().map(EntityConditions::to_document)
+        })
+    }
+
+    pub(super) fn and_untyped(
+        mut self,
+        conditions: impl Into<Option<Document>>,
+    ) -> Self {
+        self.conditions = and_conditions(self.conditions, conditions.into());
+        self
+    }
+
+    /// Restricts the query to the entities that may be written through `ctx`
+    /// with `action`, as given by [`Entity::write_filter`].
+    pub(super) fn writable(
+        self,
+        ctx: &EntityContext<T::Services>,
+        action: WriteAction,
+    ) -> Self {
+        self.and_untyped(T::write_filter(ctx, action))
+    }
+
+    pub fn skip(mut self, n: impl Into<Option<u64>>) -> Self {
+        self.options.skip = n.into();
+        self
+    }
+
+    pub fn take(mut self, n: impl Into<Option<u64>>) -> Self {
+        let n: Option<u64> = n.into();
+        self.options.limit = n.map(|n| {
+            i64::try_from(n).unwrap_or_else(|_| {
+                warn!(
+                    take = n,
+                    "take option has overflowing value; using i64::MAX instead"
+                );
+                i64::MAX
+            })
+        });
+        self
+    }
+
+    pub fn sort(mut self, sorting: impl Into<Option<T::Sorting>>) -> Self {
+        let existing = self.options.sort.take();
+        let incoming: Option<_> = sorting.into();
+        self.options.sort = match incoming {
+            Some(incoming) => {
+                let incoming = incoming.to_document();
+                let combined = match existing {
+                    Some(mut existing) => {
+                        existing.extend(incoming);
+                        existing
+                    }
+                    None => incoming,
+                };
+                Some(combined)
+            }
+            None => existing,
+        };
+        self
+    }
+
+    /// Loads the matching entities as `P`, with only the fields in
+    /// [`Object::projection`].
+    ///
+    /// Entities that opt out of [`Entity::read_filter_is_exact`] are loaded
+    /// in full instead, since [`Entity::can_read`] needs the full entity.
+    pub fn select<P>(self) -> SelectQuery<T, P>
+    where
+        P: Object + DeserializeOwned,
+    {
+        SelectQuery {
+            query: self,
+            phantom: default(),
+        }
+    }
+
+    pub async fn load(
         self,
         ctx: &EntityContext<T::Services>,
     ) -> Result<impl Stream<Item = Result<T>>> {
+        if self.checks_reads() {
+            let key_provider = ctx.key_provider();
+            let docs = self.readable_documents(ctx).await?;
+            let stream = docs.map(move |doc| {
+                let doc = doc?;
+                let key_provider = key_provider.clone();
+                with_key_provider(key_provider, || T::from_document(doc))
+            });
+            return Ok(stream.left_stream());
+        }
+        let filtered = self.filtered;
+        let cursor = self.find_documents(ctx).await?;
+        let ctx = ctx.to_owned();
+        let key_provider = ctx.key_provider();
+        let stream = cursor
+            .map(move |doc| -> Result<_> {
+                let doc = match doc {
+                    Ok(doc) => doc,
+                    Err(error) => return Err(error.into()),
+                };
+                let key_provider = key_provider.clone();
+                with_key_provider(key_provider, || T::from_document(doc))
+            })
+            .filter(move |entity| {
+                let visible = match entity {
+                    Ok(entity) => !filtered || entity.can_read(&ctx),
+                    Err(_) => true,
+                };
+                ready(visible)
+            });
+        Ok(stream.right_stream())
+    }
+
+    /// Whether [`Entity::can_read`] must be checked on the client, where the
+    /// query is then paged and counted too (see
+    /// [`Entity::read_filter_is_exact`]).
+    fn checks_reads(&self) -> bool {
+        self.filtered && !T::read_filter_is_exact()
+    }
+
+    /// Finds the documents of the matching entities that pass
+    /// [`Entity::can_read`], skipping and taking them here rather than on the
+    /// server, so that pages aren't cut short by entities that don't.
+    async fn readable_documents(
+        mut self,
+        ctx: &EntityContext<T::Services>,
+    ) -> Result<impl Stream<Item = Result<Document>>> {
+        let skip = self.options.skip.take().unwrap_or_default();
+        let take = match self.options.limit.take() {
+            Some(limit) if limit != 0 => limit.unsigned_abs(),
+            _ => u64::MAX,
+        };
+        self.options.projection = None;
+        let cursor = self.find_documents(ctx).await?;
+        let ctx = ctx.to_owned();
+        let key_provider = ctx.key_provider();
+        let stream = cursor
+            .filter_map(move |doc| {
+                let doc = || -> Result<Option<Document>> {
+                    let doc = doc?;
+                    let key_provider = key_provider.clone();
+                    let entity = with_key_provider(key_provider, || {
+                        T::from_document(doc.clone())
+                    })?;
+                    let doc = if entity.can_read(&ctx) {
+                        Some(doc)
+                    } else {
+                        None
+                    };
+                    Ok(doc)
+                };
+                ready(doc().transpose())
+            })
+            .skip(usize::try_from(skip).unwrap_or(usize::MAX))
+            .take(usize::try_from(take).unwrap_or(usize::MAX));
+        Ok(stream)
+    }
+
+    async fn find_documents(
+        self,
+        ctx: &EntityContext<T::Services>,
+    ) -> Result<DocumentCursor> {
         let Self {
             conditions,
             options,
+            scoped,
+            kept,
+            filtered,
             ..
         } = self;
+        let conditions =
+            query_conditions::<T>(ctx, conditions, scoped, kept, filtered)?;
         let collection = T::collection(ctx);
 
-        let cursor: Box<
-            dyn Stream<Item = DatabaseResult<Document>> + Send + Unpin,
-        > = if let Some(transaction) = &ctx.transaction {
+        let cursor: DocumentCursor = if let Some(handle) = ctx.session().await?
+        {
             let cursor = {
-                let mut transaction = transaction.lock().await;
-                let session = &mut transaction.session;
+                let mut session = handle.lock().await;
                 if let Some(conditions) = &conditions {
                     trace!(
                         collection = collection.name(),
@@ -627,10 +1701,10 @@ impl<T: Entity> FindQuery<T> {
                     );
                 }
                 collection
-                    .find_with_session(conditions, options, session)
+                    .find_with_session(conditions, options, &mut session)
                     .await?
             };
-            let cursor = TransactionCursor::new(cursor, transaction.to_owned());
+            let cursor = SessionStream::new(cursor, handle);
             Box::new(cursor)
         } else {
             if let Some(conditions) = &conditions {
@@ -650,81 +1724,730 @@ impl<T: Entity> FindQuery<T> {
             let cursor = collection.find(conditions, options).await?;
             Box::new(cursor)
         };
+        Ok(cursor)
+    }
+
+    /// Deletes every matching entity, running their delete callbacks.
+    ///
+    /// Matching entities are loaded so that their callbacks can run, and are
+    /// deleted a batch at a time, with a single round trip per batch.
+    pub async fn delete_all(
+        self,
+        ctx: &EntityContext<T::Services>,
+    ) -> Result<u64> {
+        ctx.with_transaction(|ctx, transaction| async move {
+            let stream = self.load(&ctx).await?;
+            pin_mut!(stream);
+            let mut deleted_count = 0;
+            loop {
+                let entities: Vec<T> = stream
+                    .as_mut()
+                    .take(DELETE_BATCH_SIZE)
+                    .try_collect()
+                    .await?;
+                if entities.is_empty() {
+                    break;
+                }
+                deleted_count +=
+                    delete_batch(&ctx, &transaction, entities).await?;
+            }
+            Ok(deleted_count)
+        })
+        .await
+    }
+
+    /// Deletes every matching entity in a single round trip, without loading
+    /// them or running their callbacks.
+    ///
+    /// Since entities aren't loaded, only those matching
+    /// [`Entity::write_filter`] are deleted, rather than checking
+    /// [`Entity::can_write`].
+    pub async fn delete_all_without_callbacks(
+        self,
+        ctx: &EntityContext<T::Services>,
+    ) -> Result<u64> {
+        let query = self.writable(ctx, WriteAction::Delete);
+        let Self {
+            conditions,
+            options,
+            scoped,
+            kept,
+            filtered,
+            ..
+        } = query;
+        ctx.with_transaction(|ctx, transaction| async move {
+            let collection = T::collection(&ctx);
+            let conditions = query_conditions::<T>(
+                &ctx, conditions, scoped, kept, filtered,
+            )?
+            .unwrap_or_default();
+
+            let mut transaction = transaction.lock().await;
+            let conditions = select_conditions(
+                &collection,
+                conditions,
+                options,
+                &mut transaction.session,
+            )
+            .await?;
+            transaction
+                .track_matching(&collection, conditions.clone())
+                .await?;
+            let auditor = T::auditor();
+            let priors = match &auditor {
+                Some(auditor) => {
+                    let transaction = &mut *transaction;
+                    let conditions = conditions.clone();
+                    auditor
+                        .load_priors(&collection, conditions, transaction)
+                        .await?
+                }
+                None => Vec::new(),
+            };
+            let Transaction { session, .. } = &mut *transaction;
+
+            trace!(
+                collection = collection.name(),
+                %conditions,
+                "deleting documents"
+            );
+            let result = collection
+                .delete_many_with_session(conditions, None, session)
+                .await?;
+            if let Some(auditor) = &auditor {
+                let transaction = &mut *transaction;
+                auditor
+                    .record_changes(&ctx, &collection, transaction, &priors)
+                    .await?;
+            }
+            Ok(result.deleted_count)
+        })
+        .await
+    }
+
+    /// Updates every matching document in a single round trip, recording the
+    /// changes in the audit log.
+    ///
+    /// The update isn't authorized; callers either authorize the entities
+    /// they load, or restrict the query with [`FindQuery::writable`].
+    pub(super) async fn update_all(
+        self,
+        ctx: &EntityContext<T::Services>,
+        update: Document,
+    ) -> Result<u64> {
+        self.update_matching(ctx, update, T::auditor()).await
+    }
+
+    /// Like [`FindQuery::update_all`], but leaves recording the changes to
+    /// the caller.
+    pub(super) async fn update_all_without_audit(
+        self,
+        ctx: &EntityContext<T::Services>,
+        update: Document,
+    ) -> Result<u64> {
+        self.update_matching(ctx, update, None).await
+    }
+
+    async fn update_matching(
+        self,
+        ctx: &EntityContext<T::Services>,
+        update: Document,
+        auditor: Option<Auditor<T>>,
+    ) -> Result<u64> {
+        let Self {
+            conditions,
+            options,
+            scoped,
+            kept,
+            filtered,
+            ..
+        } = self;
+        ctx.with_transaction(|ctx, transaction| async move {
+            let collection = T::collection(&ctx);
+            let conditions = query_conditions::<T>(
+                &ctx, conditions, scoped, kept, filtered,
+            )?
+            .unwrap_or_default();
 
-        let stream = cursor.map(|doc| -> Result<_> {
-            let doc = match doc {
-                Ok(doc) => doc,
-                Err(error) => return Err(error.into()),
+            let update = seal_conditions(&ctx, update)?;
+            let mut transaction = transaction.lock().await;
+            let conditions = select_conditions(
+                &collection,
+                conditions,
+                options,
+                &mut transaction.session,
+            )
+            .await?;
+            transaction
+                .track_matching(&collection, conditions.clone())
+                .await?;
+            let priors = match &auditor {
+                Some(auditor) => {
+                    let transaction = &mut *transaction;
+                    let conditions = conditions.clone();
+                    auditor
+                        .load_priors(&collection, conditions, transaction)
+                        .await?
+                }
+                None => Vec::new(),
             };
-            T::from_document(doc)
-        });
-        Ok(stream)
+            let Transaction { session, .. } = &mut *transaction;
+
+            trace!(
+                collection = collection.name(),
+                %conditions,
+                %update,
+                "updating documents"
+            );
+            let result = collection
+                .update_many_with_session(conditions, update, None, session)
+                .await?;
+            if let Some(auditor) = &auditor {
+                let transaction = &mut *transaction;
+                auditor
+                    .record_changes(&ctx, &collection, transaction, &priors)
+                    .await?;
+            }
+            Ok(result.modified_count)
+        })
+        .await
     }
 
+    /// The number of entities that [`FindQuery::load`] would load.
     pub async fn count(self, ctx: &EntityContext<T::Services>) -> Result<u64> {
+        if self.checks_reads() {
+            let docs = self.readable_documents(ctx).await?;
+            return docs.try_fold(0, |count, _| ready(Ok(count + 1))).await;
+        }
         let Self {
             conditions,
-            options: find_options,
+            options,
+            scoped,
+            kept,
+            filtered,
             ..
         } = self;
-        let collection = T::collection(ctx);
-
+        let conditions =
+            query_conditions::<T>(ctx, conditions, scoped, kept, filtered)?;
         let options = {
             let FindOptions {
                 limit,
                 skip,
                 collation,
                 ..
-            } = find_options.clone();
+            } = options;
             CountOptions::builder()
                 .limit(limit.map(|limit| limit as u64))
                 .skip(skip)
                 .collation(collation)
                 .build()
         };
+        count_documents::<T>(ctx, conditions, options).await
+    }
 
-        let count = if let Some(transaction) = &ctx.transaction {
-            let mut transaction = transaction.lock().await;
-            let session = &mut transaction.session;
-            if let Some(conditions) = &conditions {
-                trace!(
-                    collection = collection.name(),
-                    session = %session.id(),
-                    %conditions,
-                    options = %format_find_options(&find_options),
-                    "counting documents"
-                );
-            } else {
-                trace!(
-                    collection = collection.name(),
-                    session = %session.id(),
-                    options = %format_find_options(&find_options),
-                    "counting documents"
-                );
+    /// The distinct values of `field` among the matching entities.
+    ///
+    /// Skip, take and sorting are ignored. Entities that opt out of
+    /// [`Entity::read_filter_is_exact`] are loaded to check
+    /// [`Entity::can_read`], and their values are compared on the client,
+    /// without regard to the query's collation.
+    pub async fn distinct<V: DeserializeOwned>(
+        mut self,
+        ctx: &EntityContext<T::Services>,
+        field: &str,
+    ) -> Result<Vec<V>> {
+        let values = if self.checks_reads() {
+            self.options.skip = None;
+            self.options.limit = None;
+            let mut values = Vec::new();
+            let mut seen = HashSet::new();
+            let docs = self.readable_documents(ctx).await?;
+            pin_mut!(docs);
+            while let Some(doc) = docs.try_next().await? {
+                for value in field_values(&doc, field) {
+                    if seen.insert(value.to_string()) {
+                        values.push(value);
+                    }
+                }
             }
+            values
+        } else {
+            self.find_distinct(ctx, field).await?
+        };
+
+        ctx.with_encryption(|| {
+            values
+                .into_iter()
+                .map(|value| {
+                    bson::from_bson(value)
+                        .context("failed to deserialize value")
+                })
+                .collect()
+        })
+    }
+
+    async fn find_distinct(
+        self,
+        ctx: &EntityContext<T::Services>,
+        field: &str,
+    ) -> Result<Vec<Bson>> {
+        let Self {
+            conditions,
+            options,
+            scoped,
+            kept,
+            filtered,
+            ..
+        } = self;
+        let conditions =
+            query_conditions::<T>(ctx, conditions, scoped, kept, filtered)?;
+        let collection = T::collection(ctx);
+        let options = DistinctOptions::builder()
+            .collation(options.collation)
+            .build();
+
+        let filter = conditions.clone().unwrap_or_default();
+        let values = if let Some(session) = ctx.session().await? {
+            let mut session = session.lock().await;
+            trace!(
+                collection = collection.name(),
+                session = %session.id(),
+                field,
+                conditions = %filter,
+                "finding distinct values"
+            );
             collection
-                .count_documents_with_session(conditions, options, session)
+                .distinct_with_session(field, conditions, options, &mut session)
                 .await?
         } else {
-            if let Some(conditions) = &conditions {
-                trace!(
-                    collection = collection.name(),
-                    %conditions,
-                    options = %format_find_options(&find_options),
-                    "counting documents"
-                );
-            } else {
-                trace!(
-                    collection = collection.name(),
-                    options = %format_find_options(&find_options),
-                    "counting documents"
-                );
+            trace!(
+                collection = collection.name(),
+                field,
+                conditions = %filter,
+                "finding distinct values"
+            );
+            collection.distinct(field, conditions, options).await?
+        };
+        Ok(values)
+    }
+
+    /// The number of matching entities for each distinct value of `field`,
+    /// most frequent first.
+    ///
+    /// Entities without a value for `field` are counted under null, so `V`
+    /// should be an `Option` unless every entity has one. As with
+    /// [`FindQuery::distinct`], entities that opt out of
+    /// [`Entity::read_filter_is_exact`] are counted on the client.
+    pub async fn count_by<V: DeserializeOwned>(
+        self,
+        ctx: &EntityContext<T::Services>,
+        field: &str,
+    ) -> Result<Vec<(V, u64)>> {
+        let counts = if self.checks_reads() {
+            let mut counts: Vec<(Bson, u64)> = Vec::new();
+            let mut indices = HashMap::new();
+            let docs = self.readable_documents(ctx).await?;
+            pin_mut!(docs);
+            while let Some(doc) = docs.try_next().await? {
+                let value = group_value(&doc, field);
+                let index =
+                    *indices.entry(value.to_string()).or_insert_with(|| {
+                        counts.push((value, 0));
+                        counts.len() - 1
+                    });
+                counts[index].1 += 1;
+            }
+            counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+            counts
+        } else {
+            self.find_grouped(ctx, field).await?
+        };
+
+        ctx.with_encryption(|| {
+            counts
+                .into_iter()
+                .map(|(value, count)| {
+                    let value = bson::from_bson(value)
+                        .context("failed to deserialize value")?;
+                    Ok((value, count))
+                })
+                .collect()
+        })
+    }
+
+    async fn find_grouped(
+        self,
+        ctx: &EntityContext<T::Services>,
+        field: &str,
+    ) -> Result<Vec<(Bson, u64)>> {
+        let Self {
+            conditions,
+            options,
+            scoped,
+            kept,
+            filtered,
+            ..
+        } = self;
+        let pipeline = {
+            let conditions =
+                query_conditions::<T>(ctx, conditions, scoped, kept, filtered)?;
+            let FindOptions {
+                sort, skip, limit, ..
+            } = &options;
+            let mut pipeline = Vec::new();
+            if let Some(conditions) = conditions {
+                pipeline.push(doc! { "$match": conditions });
+            }
+            if let Some(sort) = sort {
+                pipeline.push(doc! { "$sort": sort });
+            }
+            if let Some(skip) = skip {
+                let skip = i64::try_from(*skip).unwrap_or(i64::MAX);
+                pipeline.push(doc! { "$skip": skip });
+            }
+            // As with find, a limit of 0 means no limit at all, which isn't a
+            // valid $limit.
+            if let Some(limit) = limit.filter(|&limit| limit != 0) {
+                pipeline.push(doc! { "$limit": limit });
+            }
+            pipeline
+        };
+        let options = AggregateOptions::builder()
+            .collation(options.collation)
+            .build();
+
+        count_grouped::<T>(ctx, pipeline, field, options).await
+    }
+}
+
+/// The values of `field` (a dotted path) in `doc`, with arrays unwound, as
+/// the server finds them for `distinct`.
+fn field_values(doc: &Document, field: &str) -> Vec<Bson> {
+    fn collect(value: &Bson, path: &[&str], values: &mut Vec<Bson>) {
+        match (value, path.split_first()) {
+            (Bson::Array(array), None) => values.extend(array.iter().cloned()),
+            (Bson::Array(array), Some(_)) => {
+                for value in array {
+                    collect(value, path, values);
+                }
+            }
+            (value, None) => values.push(value.clone()),
+            (Bson::Document(doc), Some((key, path))) => {
+                if let Some(value) = doc.get(*key) {
+                    collect(value, path, values);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let path: Vec<&str> = field.split('.').collect();
+    let mut values = Vec::new();
+    if let Some((key, path)) = path.split_first() {
+        if let Some(value) = doc.get(*key) {
+            collect(value, path, &mut values);
+        }
+    }
+    values
+}
+
+/// The value of `field` (a dotted path) in `doc` that the server groups it
+/// under for `$group`, which is null if it's missing.
+fn group_value(doc: &Document, field: &str) -> Bson {
+    fn find(value: &Bson, path: &[&str]) -> Option<Bson> {
+        let (key, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return Some(value.clone()),
+        };
+        match value {
+            Bson::Document(doc) => find(doc.get(*key)?, rest),
+            Bson::Array(array) => {
+                let values = array.iter().filter_map(|value| find(value, path));
+                Some(Bson::Array(values.collect()))
+            }
+            _ => None,
+        }
+    }
+
+    let path: Vec<&str> = field.split('.').collect();
+    let value = path
+        .split_first()
+        .and_then(|(key, path)| find(doc.get(*key)?, path));
+    value.unwrap_or(Bson::Null)
+}
+
+/// Deletes `entities` with a single round trip, running their delete
+/// callbacks.
+async fn delete_batch<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+    transaction: &Mutex<Transaction>,
+    mut entities: Vec<T>,
+) -> Result<u64> {
+    let collection = T::collection(ctx);
+    for entity in &entities {
+        authorize_write(ctx, entity, WriteAction::Delete)?;
+    }
+    for entity in &mut entities {
+        entity.before_delete(ctx).await?;
+    }
+    for entity in &entities {
+        handle_dependents(ctx, entity, DependentEvent::Delete).await?;
+    }
+
+    let deleted_count = {
+        let ids = entities
+            .iter()
+            .map(|entity| Bson::from(entity.id()))
+            .collect::<Vec<_>>();
+        let mut transaction = transaction.lock().await;
+        transaction.track_many(&collection, ids.clone()).await?;
+        let Transaction {
+            session,
+            commit_finalizers,
+            abort_finalizers,
+            ..
+        } = &mut *transaction;
+        for entity in &entities {
+            {
+                let entity = entity.clone();
+                let ctx = ctx.clone();
+                let finalizer =
+                    async move { entity.after_delete_commit(&ctx).await };
+                commit_finalizers.push(finalizer.boxed());
+            }
+            {
+                let entity = entity.clone();
+                let ctx = ctx.clone();
+                let finalizer =
+                    async move { entity.after_delete_abort(&ctx).await };
+                abort_finalizers.push(finalizer.boxed());
             }
-            collection.count_documents(conditions, None).await?
+        }
+
+        let conditions = doc! { "_id": { "$in": ids } };
+        trace!(
+            collection = collection.name(),
+            %conditions,
+            "deleting documents"
+        );
+        let result = collection
+            .delete_many_with_session(conditions, None, session)
+            .await?;
+        if let Some(auditor) = T::auditor() {
+            let transaction = &mut *transaction;
+            for entity in &entities {
+                let before = ctx
+                    .with_encryption(|| entity.to_document())
+                    .context("failed to serialize record")?;
+                let (id, before) = (entity.id(), Some(&before));
+                let operation = AuditOperation::Delete;
+                auditor
+                    .record(ctx, transaction, id, operation, before, None)
+                    .await?;
+            }
+        }
+        result.deleted_count
+    };
+
+    for entity in &mut entities {
+        entity.after_delete(ctx).await?;
+    }
+    Ok(deleted_count)
+}
+
+/// Loads matching entities as `P`; see [`FindQuery::select`].
+pub struct SelectQuery<T: Entity, P: Object> {
+    query: FindQuery<T>,
+    phantom: PhantomData<P>,
+}
+
+impl<T: Entity, P: Object + DeserializeOwned> SelectQuery<T, P> {
+    pub async fn load(
+        self,
+        ctx: &EntityContext<T::Services>,
+    ) -> Result<impl Stream<Item = Result<P>>> {
+        let Self { mut query, .. } = self;
+        let key_provider = ctx.key_provider();
+        let object = move |doc: Document| -> Result<P> {
+            let key_provider = key_provider.clone();
+            with_key_provider(key_provider, || P::from_document(doc))
+                .context("failed to deserialize object")
+        };
+        if query.checks_reads() {
+            let docs = query.readable_documents(ctx).await?;
+            let stream = docs.map(move |doc| object(doc?));
+            return Ok(stream.left_stream());
+        }
+        query.options.projection = P::projection();
+        let cursor = query.find_documents(ctx).await?;
+        let stream = cursor.map(move |doc| object(doc?));
+        Ok(stream.right_stream())
+    }
+}
+
+fn and_conditions(
+    existing: Option<Document>,
+    incoming: Option<Document>,
+) -> Option<Document> {
+    match (existing, incoming) {
+        (Some(existing), Some(incoming)) => Some(doc! {
+            "$and": [existing, incoming],
+        }),
+        (existing, None) => existing,
+        (None, incoming) => incoming,
+    }
+}
+
+/// Restricts `conditions` to the entities of `T` visible through `ctx`, and
+/// to its default scope (leaving out discarded entities), if `scoped`.
+///
+/// [`Deterministic`] values in `conditions` are encrypted along the way.
+pub(super) fn scope_conditions<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+    conditions: Option<Document>,
+    scoped: bool,
+) -> Result<Option<Document>> {
+    query_conditions::<T>(ctx, conditions, scoped, true, true)
+}
+
+/// Like [`scope_conditions`], but leaves the discarder out of the default
+/// scope unless `kept`, and disregards the read filter unless `filtered`.
+fn query_conditions<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+    conditions: Option<Document>,
+    scoped: bool,
+    kept: bool,
+    filtered: bool,
+) -> Result<Option<Document>> {
+    let conditions = if filtered {
+        and_conditions(T::read_filter(ctx), conditions)
+    } else {
+        conditions
+    };
+    let conditions = default_scope_conditions::<T>(conditions, scoped, kept);
+    conditions
+        .map(|conditions| seal_conditions(ctx, conditions))
+        .transpose()
+}
+
+/// The conditions that restrict a query of `T` to its default scope and
+/// read filter, like [`scope_conditions`], but with [`Deterministic`] values
+/// left to be encrypted when the query that embeds them runs.
+pub(super) fn embedded_scope_conditions<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+) -> Option<Document> {
+    default_scope_conditions::<T>(T::read_filter(ctx), true, true)
+}
+
+/// Restricts `conditions` to the default scope of `T`, if `scoped`, leaving
+/// out discarded entities if `kept`.
+fn default_scope_conditions<T: Entity>(
+    conditions: Option<Document>,
+    scoped: bool,
+    kept: bool,
+) -> Option<Document> {
+    let scope = if scoped {
+        let kept = match T::discarder() {
+            Some(discarder) if kept => Some(discarder.kept_conditions()),
+            _ => None,
         };
+        and_conditions(T::default_scope(), kept)
+    } else {
+        None
+    };
+    and_conditions(scope, conditions)
+}
+
+/// Prepends a stage to `pipeline` that restricts it to the default scope of
+/// `T`, if `scoped`.
+///
+/// The stage goes after a leading `$geoNear`, which must be the first stage
+/// of a pipeline. [`Deterministic`] values in `pipeline` are encrypted along
+/// the way.
+fn scope_pipeline<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+    pipeline: Vec<Document>,
+    scoped: bool,
+) -> Result<Vec<Document>> {
+    let mut pipeline = pipeline
+        .into_iter()
+        .map(|stage| seal_conditions(ctx, stage))
+        .collect::<Result<Vec<_>>>()?;
+    let scope = match scope_conditions::<T>(ctx, None, scoped)? {
+        Some(scope) => scope,
+        None => return Ok(pipeline),
+    };
+    let stage = doc! { "$match": scope };
+    let index = match pipeline.first() {
+        Some(first) if first.contains_key("$geoNear") => 1,
+        _ => 0,
+    };
+    pipeline.insert(index, stage);
+    Ok(pipeline)
+}
+
+/// Loads the stored document with `id`, which a write by id would overwrite
+/// or remove.
+async fn load_prior(
+    collection: &Collection<Document>,
+    id: impl Into<Bson>,
+    session: &mut DatabaseSession,
+) -> Result<Option<Document>> {
+    let conditions = doc! { "_id": id.into() };
+    let doc = collection
+        .find_one_with_session(conditions, None, session)
+        .await
+        .context("failed to load prior state")?;
+    Ok(doc)
+}
+
+/// Finds the first document that matches `conditions` in `sort` order, which
+/// is the one a find-and-modify command with the same arguments modifies.
+async fn find_target(
+    collection: &Collection<Document>,
+    conditions: Document,
+    sort: Option<Document>,
+    collation: Option<Collation>,
+    session: &mut DatabaseSession,
+) -> Result<Option<Document>> {
+    let options = FindOneOptions::builder()
+        .sort(sort)
+        .collation(collation)
+        .build();
+    let doc = collection
+        .find_one_with_session(conditions, options, session)
+        .await
+        .context("failed to find document")?;
+    Ok(doc)
+}
 
-        Ok(count)
+/// Narrows `conditions` down to the ids of the documents selected by
+/// `options`, if it skips or limits the documents it matches.
+async fn select_conditions(
+    collection: &Collection<Document>,
+    conditions: Document,
+    options: FindOptions,
+    session: &mut DatabaseSession,
+) -> Result<Document> {
+    if options.skip.is_none() && options.limit.is_none() {
+        return Ok(conditions);
+    }
+
+    let options = {
+        let mut options = options;
+        options.projection = Some(doc! { "_id": 1 });
+        options
+    };
+    let mut cursor = collection
+        .find_with_session(conditions, options, session)
+        .await?;
+    let mut ids = Vec::new();
+    while let Some(doc) = cursor.next(session).await {
+        let doc = doc?;
+        let id = doc.get("_id").context("missing document id")?;
+        ids.push(id.to_owned());
     }
+    Ok(doc! { "_id": { "$in": ids } })
 }
 
 #[derive(Debug, Clone)]
@@ -743,9 +2466,15 @@ impl<T: Entity, U: Object> AggregateOneQuery<T, U> {
         MaybeAggregateOneQuery(inner)
     }
 
+    pub fn unscoped(self) -> Self {
+        let Self(inner) = self;
+        Self(inner.unscoped())
+    }
+
     pub async fn load(self, ctx: &EntityContext<T::Services>) -> Result<U> {
         let Self(inner) = self;
-        inner.load(ctx).await?.context("not found")
+        let entity = inner.load(ctx).await?;
+        entity.ok_or_else(|| NotFoundError::new::<T>().into())
     }
 }
 
@@ -765,6 +2494,11 @@ impl<T: Entity, U: Object> MaybeAggregateOneQuery<T, U> {
         AggregateOneQuery(inner)
     }
 
+    pub fn unscoped(self) -> Self {
+        let Self(inner) = self;
+        Self(inner.unscoped())
+    }
+
     pub async fn load(
         self,
         ctx: &EntityContext<T::Services>,
@@ -780,6 +2514,7 @@ struct AggregateOneQueryInner<T: Entity, U: Object> {
     phantom_entity: PhantomData<T>,
     phantom_object: PhantomData<U>,
     options: AggregateOptions,
+    scoped: bool,
 }
 
 impl<T: Entity, U: Object> AggregateOneQueryInner<T, U> {
@@ -791,20 +2526,29 @@ impl<T: Entity, U: Object> AggregateOneQueryInner<T, U> {
             phantom_entity: default(),
             phantom_object: default(),
             options,
+            scoped: true,
         }
     }
 
+    pub fn unscoped(mut self) -> Self {
+        self.scoped = false;
+        self
+    }
+
     pub async fn load(
         self,
         ctx: &EntityContext<T::Services>,
     ) -> Result<Option<U>> {
         let Self {
-            options, pipeline, ..
+            options,
+            pipeline,
+            scoped,
+            ..
         } = self;
         let collection = T::collection(ctx);
 
         let pipeline = {
-            let mut pipeline = pipeline;
+            let mut pipeline = scope_pipeline::<T>(ctx, pipeline, scoped)?;
             pipeline.push(doc! {
                 "$limit": 1
             });
@@ -813,10 +2557,9 @@ impl<T: Entity, U: Object> AggregateOneQueryInner<T, U> {
 
         let mut cursor: Box<
             dyn Stream<Item = DatabaseResult<Document>> + Send + Unpin,
-        > = if let Some(transaction) = &ctx.transaction {
+        > = if let Some(handle) = ctx.session().await? {
             let cursor = {
-                let mut transaction = transaction.lock().await;
-                let session = &mut transaction.session;
+                let mut session = handle.lock().await;
                 trace!(
                     collection = collection.name(),
                     session = %session.id(),
@@ -824,10 +2567,10 @@ impl<T: Entity, U: Object> AggregateOneQueryInner<T, U> {
                     "aggregating documents"
                 );
                 collection
-                    .aggregate_with_session(pipeline, options, session)
+                    .aggregate_with_session(pipeline, options, &mut session)
                     .await?
             };
-            let cursor = TransactionCursor::new(cursor, transaction.to_owned());
+            let cursor = SessionStream::new(cursor, handle);
             Box::new(cursor)
         } else {
             trace!(
@@ -842,7 +2585,7 @@ impl<T: Entity, U: Object> AggregateOneQueryInner<T, U> {
         let doc = cursor.next().await;
         let doc = doc.transpose()?;
         let object = doc
-            .map(U::from_document)
+            .map(|doc| ctx.with_encryption(|| U::from_document(doc)))
             .transpose()
             .context("failed to deserialize object")?;
         Ok(object)
@@ -857,6 +2600,7 @@ pub struct AggregateQuery<T: Entity, U: Object> {
     options: AggregateOptions,
     skip: Option<u32>,
     take: Option<u32>,
+    scoped: bool,
 }
 
 impl<T: Entity, U: Object> AggregateQuery<T, U> {
@@ -870,9 +2614,15 @@ impl<T: Entity, U: Object> AggregateQuery<T, U> {
             options,
             skip: default(),
             take: default(),
+            scoped: true,
         }
     }
 
+    pub fn unscoped(mut self) -> Self {
+        self.scoped = false;
+        self
+    }
+
     pub fn skip(mut self, n: impl Into<Option<u32>>) -> Self {
         self.skip = n.into();
         self
@@ -883,7 +2633,7 @@ impl<T: Entity, U: Object> AggregateQuery<T, U> {
         self
     }
 
-    pub async fn load<'a>(
+    pub async fn load(
         self,
         ctx: &EntityContext<T::Services>,
     ) -> Result<impl Stream<Item = Result<U>>> {
@@ -892,12 +2642,13 @@ impl<T: Entity, U: Object> AggregateQuery<T, U> {
             options,
             skip,
             take,
+            scoped,
             ..
         } = self;
         let collection = T::collection(ctx);
 
         let pipeline = {
-            let mut pipeline = pipeline;
+            let mut pipeline = scope_pipeline::<T>(ctx, pipeline, scoped)?;
             if let Some(skip) = skip {
                 pipeline.push(doc! {
                     "$skip": skip
@@ -913,10 +2664,9 @@ impl<T: Entity, U: Object> AggregateQuery<T, U> {
 
         let cursor: Box<
             dyn Stream<Item = DatabaseResult<Document>> + Send + Unpin,
-        > = if let Some(transaction) = &ctx.transaction {
+        > = if let Some(handle) = ctx.session().await? {
             let cursor = {
-                let mut transaction = transaction.lock().await;
-                let session = &mut transaction.session;
+                let mut session = handle.lock().await;
                 trace!(
                     collection = collection.name(),
                     session = %session.id(),
@@ -925,10 +2675,10 @@ impl<T: Entity, U: Object> AggregateQuery<T, U> {
                     "aggregating documents"
                 );
                 collection
-                    .aggregate_with_session(pipeline, options, session)
+                    .aggregate_with_session(pipeline, options, &mut session)
                     .await?
             };
-            let cursor = TransactionCursor::new(cursor, transaction.to_owned());
+            let cursor = SessionStream::new(cursor, handle);
             Box::new(cursor)
         } else {
             trace!(
@@ -941,28 +2691,27 @@ impl<T: Entity, U: Object> AggregateQuery<T, U> {
             Box::new(cursor)
         };
 
-        let stream = cursor.map(|result| -> Result<U> {
+        let key_provider = ctx.key_provider();
+        let stream = cursor.map(move |result| -> Result<U> {
             let doc = result?;
-            U::from_document(doc).context("failed to deserialize object")
+            let key_provider = key_provider.clone();
+            with_key_provider(key_provider, || U::from_document(doc))
+                .context("failed to deserialize object")
         });
         Ok(stream)
     }
 
-    pub async fn count<'a>(
-        self,
-        ctx: &EntityContext<T::Services>,
-    ) -> Result<u64> {
+    pub async fn count(self, ctx: &EntityContext<T::Services>) -> Result<u64> {
         let Self {
             pipeline,
             options,
             skip,
             take,
+            scoped,
             ..
         } = self;
-        let collection = T::collection(ctx);
-
         let pipeline = {
-            let mut pipeline = pipeline;
+            let mut pipeline = scope_pipeline::<T>(ctx, pipeline, scoped)?;
             if let Some(skip) = skip {
                 pipeline.push(doc! {
                     "$skip": skip
@@ -973,93 +2722,9 @@ impl<T: Entity, U: Object> AggregateQuery<T, U> {
                     "$limit": take
                 });
             }
-            pipeline.push(doc! {
-                "$count": "_count"
-            });
             pipeline
         };
-
-        let result: Document = if let Some(transaction) = &ctx.transaction {
-            let mut transaction = transaction.lock().await;
-            let session = &mut transaction.session;
-            trace!(
-                collection = collection.name(),
-                session = %session.id(),
-                pipeline = %format_pipeline(&pipeline),
-                options = %format_aggregate_options(&options),
-                "counting aggregated documents"
-            );
-            let mut cursor = {
-                collection
-                    .aggregate_with_session(pipeline, options, session)
-                    .await?
-            };
-            cursor.next(session).await.unwrap()?
-        } else {
-            trace!(
-                collection = collection.name(),
-                pipeline = %format_pipeline(&pipeline),
-                options = %format_aggregate_options(&options),
-                "counting aggregated documents"
-            );
-            let mut cursor = collection.aggregate(pipeline, options).await?;
-            cursor.next().await.unwrap()?
-        };
-
-        let count = result.get_i64("_count").unwrap();
-        let count = u64::try_from(count).unwrap();
-        Ok(count)
-    }
-}
-
-#[pin_project]
-#[derive(Debug)]
-struct TransactionCursor<T>
-where
-    T: DeserializeOwned + Unpin,
-    T: Send + Sync,
-{
-    cursor: SessionCursor<T>,
-    transaction: Arc<Mutex<Transaction>>,
-}
-
-impl<T> TransactionCursor<T>
-where
-    T: DeserializeOwned + Unpin,
-    T: Send + Sync,
-{
-    fn new(
-        cursor: SessionCursor<T>,
-        transaction: Arc<Mutex<Transaction>>,
-    ) -> Self {
-        Self {
-            cursor,
-            transaction,
-        }
-    }
-
-    async fn next(self: Pin<&mut Self>) -> Option<DatabaseResult<T>> {
-        let projection = self.project();
-        let mut transaction = projection.transaction.lock().await;
-        let session = &mut transaction.session;
-        projection.cursor.next(session).await
-    }
-}
-
-impl<T> Stream for TransactionCursor<T>
-where
-    T: DeserializeOwned + Unpin,
-    T: Send + Sync,
-{
-    type Item = DatabaseResult<T>;
-
-    fn poll_next(
-        self: Pin<&mut Self>,
-        cx: &mut TaskContext<'_>,
-    ) -> TaskPoll<Option<Self::Item>> {
-        let future = self.next();
-        pin_mut!(future);
-        future.poll(cx)
+        count_aggregated::<T>(ctx, pipeline, options).await
     }
 }
 
@@ -1072,11 +2737,13 @@ fn format_find_options(options: &FindOptions) -> impl Display {
     to_document(options).unwrap()
 }
 
-fn format_aggregate_options(options: &AggregateOptions) -> impl Display {
+pub(super) fn format_aggregate_options(
+    options: &AggregateOptions,
+) -> impl Display {
     to_document(options).unwrap()
 }
 
-fn format_pipeline(pipeline: &[Document]) -> impl Display {
+pub(super) fn format_pipeline(pipeline: &[Document]) -> impl Display {
     let pipeline = pipeline.iter().cloned().map(Bson::from).collect::<Vec<_>>();
     Bson::Array(pipeline)
 }
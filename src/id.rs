@@ -2,22 +2,41 @@ use super::*;
 
 pub use bson::oid::ObjectId;
 
-#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Derivative)]
+#[derivative(
+    Clone(bound = ""),
+    Hash(bound = ""),
+    PartialEq(bound = ""),
+    Eq(bound = ""),
+    PartialOrd(bound = ""),
+    Ord(bound = "")
+)]
 pub struct EntityId<T: Entity> {
-    inner: ObjectId,
+    inner: T::Key,
     _phantom: PhantomData<T>,
 }
 
-impl<T: Entity> Copy for EntityId<T> {}
-
 impl<T: Entity> EntityId<T> {
     pub fn new() -> Self {
-        let inner = ObjectId::new();
+        let inner = T::Key::generate();
+        Self::from_key(inner)
+    }
+
+    pub fn from_key(key: T::Key) -> Self {
         Self {
-            inner,
+            inner: key,
             _phantom: default(),
         }
     }
+
+    pub fn key(&self) -> T::Key {
+        self.inner.clone()
+    }
+
+    pub fn from_bson(bson: &Bson) -> Result<Self> {
+        let key = T::Key::from_bson(bson)?;
+        Ok(Self::from_key(key))
+    }
 }
 
 impl<T: Entity> Default for EntityId<T> {
@@ -26,16 +45,13 @@ impl<T: Entity> Default for EntityId<T> {
     }
 }
 
-impl<T: Entity> From<ObjectId> for EntityId<T> {
+impl<T: Entity<Key = ObjectId>> From<ObjectId> for EntityId<T> {
     fn from(id: ObjectId) -> Self {
-        EntityId {
-            inner: id,
-            _phantom: default(),
-        }
+        Self::from_key(id)
     }
 }
 
-impl<T: Entity> From<EntityId<T>> for ObjectId {
+impl<T: Entity<Key = ObjectId>> From<EntityId<T>> for ObjectId {
     fn from(id: EntityId<T>) -> Self {
         id.inner
     }
@@ -43,8 +59,7 @@ impl<T: Entity> From<EntityId<T>> for ObjectId {
 
 impl<T: Entity> From<EntityId<T>> for Bson {
     fn from(id: EntityId<T>) -> Self {
-        let id: ObjectId = id.into();
-        id.into()
+        id.inner.to_bson()
     }
 }
 
@@ -57,61 +72,125 @@ impl<T: Entity> Debug for EntityId<T> {
 
 impl<T: Entity> Display for EntityId<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-        let s = encode_base64(format!("{:?}", self));
+        let s = T::id_codec().encode(self);
         f.write_str(&s)
     }
 }
 
+/// Serializes to the key itself (i.e. a native `ObjectId`) in entity
+/// documents, which are serialized within [`EntityContext::with_encryption`]
+/// (or [`with_key_provider`]), and with serializers that aren't
+/// human-readable. Serializes to a string encoded with [`Entity::id_codec`]
+/// otherwise (i.e. in JSON).
+///
+/// To store the key itself in other BSON documents, use
+/// [`entity_id_as_key`].
 impl<T: Entity> Serialize for EntityId<T> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let s = self.to_string();
-        s.serialize(serializer)
+        if in_document() || !serializer.is_human_readable() {
+            let key = self.inner.to_bson();
+            key.serialize(serializer)
+        } else {
+            let s = self.to_string();
+            s.serialize(serializer)
+        }
     }
 }
 
+/// Deserializes from either an encoded string or the key itself, regardless
+/// of the format.
 impl<'de, T: Entity> Deserialize<'de> for EntityId<T> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let s = String::deserialize(deserializer)?;
-        s.parse().map_err(|error| {
+        let bson = Bson::deserialize(deserializer)?;
+        parse_id_bson(&bson).map_err(|error| {
             let message = format!("{:?}", error);
             D::Error::custom(message)
         })
     }
 }
 
+fn parse_id_bson<T: Entity>(bson: &Bson) -> Result<EntityId<T>> {
+    if let Bson::String(s) = bson {
+        // Keys that are themselves strings may not be encoded.
+        if let Ok(id) = s.parse() {
+            return Ok(id);
+        }
+    }
+    EntityId::from_bson(bson)
+}
+
+/// Serde helpers to store an [`EntityId`] as its key (i.e. a native
+/// `ObjectId`) in a BSON document, even though `bson`'s serializer is
+/// human-readable:
+///
+/// ```ignore
+/// #[serde(rename = "_id", with = "entity_id_as_key")]
+/// pub id: EntityId<User>,
+/// ```
+///
+/// Encoded strings are still accepted when deserializing.
+pub mod entity_id_as_key {
+    use super::*;
+
+    pub fn serialize<T, S>(
+        id: &EntityId<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        T: Entity,
+        S: Serializer,
+    {
+        id.inner.to_bson().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T, D>(
+        deserializer: D,
+    ) -> Result<EntityId<T>, D::Error>
+    where
+        T: Entity,
+        D: Deserializer<'de>,
+    {
+        EntityId::deserialize(deserializer)
+    }
+}
+
+/// Like [`entity_id_as_key`], for an `Option<EntityId<T>>`.
+pub mod option_entity_id_as_key {
+    use super::*;
+
+    pub fn serialize<T, S>(
+        id: &Option<EntityId<T>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        T: Entity,
+        S: Serializer,
+    {
+        let key = id.as_ref().map(|id| id.inner.to_bson());
+        key.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T, D>(
+        deserializer: D,
+    ) -> Result<Option<EntityId<T>>, D::Error>
+    where
+        T: Entity,
+        D: Deserializer<'de>,
+    {
+        Option::<EntityId<T>>::deserialize(deserializer)
+    }
+}
+
 impl<T: Entity> FromStr for EntityId<T> {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s = {
-            let data = decode_base64(s).context("failed to decode base64")?;
-            String::from_utf8_lossy(&data[..]).into_owned()
-        };
-        let segments = s.split(':').collect::<Vec<_>>();
-
-        let (id, entity_name) = match segments[..] {
-            [entity_name, id] => {
-                let id: ObjectId =
-                    id.parse().context("failed to parse ObjectId")?;
-                (id, entity_name.to_owned())
-            }
-            _ => bail!("bad format"),
-        };
-        if entity_name != T::NAME {
-            bail!(
-                "incorrect entity name: expected {}, got {}",
-                T::NAME,
-                entity_name
-            );
-        }
-
-        let id = Self::from(id);
-        Ok(id)
+        T::id_codec().decode(s)
     }
 }
@@ -0,0 +1,253 @@
+use super::*;
+
+use bson::spec::BinarySubtype;
+use bson::Binary;
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use std::hash::Hash;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use uuid::Uuid;
+
+/// A type that can be used as the key of an [`EntityId`], and stored as the
+/// `_id` of an entity's document.
+pub trait EntityKey
+where
+    Self: Send + Sync + 'static,
+    Self: Clone + Hash + Eq + Ord,
+    Self: Debug + Display,
+{
+    fn generate() -> Self;
+    fn parse(s: &str) -> Result<Self>;
+
+    fn to_bson(&self) -> Bson;
+    fn from_bson(bson: &Bson) -> Result<Self>;
+}
+
+impl EntityKey for ObjectId {
+    fn generate() -> Self {
+        ObjectId::new()
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        s.parse().context("failed to parse ObjectId")
+    }
+
+    fn to_bson(&self) -> Bson {
+        Bson::ObjectId(*self)
+    }
+
+    fn from_bson(bson: &Bson) -> Result<Self> {
+        match bson {
+            Bson::ObjectId(id) => Ok(*id),
+            _ => bail!("expected ObjectId, got {}", bson),
+        }
+    }
+}
+
+/// Stored as a BSON binary of the UUID subtype.
+impl EntityKey for Uuid {
+    fn generate() -> Self {
+        Uuid::new_v4()
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        s.parse().context("failed to parse UUID")
+    }
+
+    fn to_bson(&self) -> Bson {
+        let binary = Binary {
+            subtype: BinarySubtype::Uuid,
+            bytes: self.as_bytes().to_vec(),
+        };
+        Bson::Binary(binary)
+    }
+
+    fn from_bson(bson: &Bson) -> Result<Self> {
+        match bson {
+            Bson::Binary(Binary {
+                subtype: BinarySubtype::Uuid,
+                bytes,
+            }) => Uuid::from_slice(bytes).context("invalid UUID"),
+            _ => bail!("expected UUID, got {}", bson),
+        }
+    }
+}
+
+const ULID_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const ULID_LEN: usize = 26;
+
+/// A universally unique, lexicographically sortable identifier, made of a
+/// millisecond timestamp and 80 random bits.
+///
+/// Stored as its canonical (Crockford base32) string, so that documents
+/// keyed by ULIDs sort by creation time.
+#[derive(Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ulid(u128);
+
+impl Ulid {
+    pub fn new() -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis());
+        let mut random = [0u8; 16];
+        SystemRandom::new()
+            .fill(&mut random[6..])
+            .expect("failed to generate random bits");
+        let random = u128::from_be_bytes(random);
+        Self(timestamp << 80 | random)
+    }
+
+    pub fn timestamp_ms(&self) -> u64 {
+        let Self(value) = self;
+        (value >> 80) as u64
+    }
+}
+
+impl Default for Ulid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for Ulid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "Ulid({})", self)
+    }
+}
+
+impl Display for Ulid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Self(value) = self;
+        let mut chars = [0u8; ULID_LEN];
+        for (index, char) in chars.iter_mut().rev().enumerate() {
+            let digit = (value >> (5 * index)) & 0x1f;
+            *char = ULID_ALPHABET[digit as usize];
+        }
+        let s = String::from_utf8_lossy(&chars);
+        f.write_str(&s)
+    }
+}
+
+impl FromStr for Ulid {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != ULID_LEN {
+            bail!("bad length: expected {}, got {}", ULID_LEN, s.len());
+        }
+        let mut value: u128 = 0;
+        for (index, char) in s.bytes().enumerate() {
+            let char = char.to_ascii_uppercase();
+            let digit = ULID_ALPHABET
+                .iter()
+                .position(|&candidate| candidate == char)
+                .with_context(|| format!("bad character at {}", index))?;
+            if index == 0 && digit > 7 {
+                bail!("overflowing value");
+            }
+            value = value << 5 | digit as u128;
+        }
+        Ok(Self(value))
+    }
+}
+
+impl EntityKey for Ulid {
+    fn generate() -> Self {
+        Ulid::new()
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        s.parse().context("failed to parse ULID")
+    }
+
+    fn to_bson(&self) -> Bson {
+        Bson::String(self.to_string())
+    }
+
+    fn from_bson(bson: &Bson) -> Result<Self> {
+        match bson {
+            Bson::String(s) => Self::parse(s),
+            _ => bail!("expected ULID, got {}", bson),
+        }
+    }
+}
+
+/// Stored as a BSON string; generated keys are ULIDs, but keys can be any
+/// string (i.e. natural keys like usernames).
+impl EntityKey for String {
+    fn generate() -> Self {
+        Ulid::new().to_string()
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        Ok(s.to_owned())
+    }
+
+    fn to_bson(&self) -> Bson {
+        Bson::String(self.to_owned())
+    }
+
+    fn from_bson(bson: &Bson) -> Result<Self> {
+        match bson {
+            Bson::String(s) => Ok(s.to_owned()),
+            _ => bail!("expected string, got {}", bson),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_ulids_as_crockford_base32() {
+        assert_eq!(Ulid(0).to_string(), "00000000000000000000000000");
+        assert_eq!(Ulid(u128::MAX).to_string(), "7ZZZZZZZZZZZZZZZZZZZZZZZZZ");
+        assert_eq!(Ulid(31).to_string(), "0000000000000000000000000Z");
+    }
+
+    #[test]
+    fn round_trips_ulids() -> Result<()> {
+        let ulid = Ulid::new();
+        assert_eq!(ulid.to_string().parse::<Ulid>()?, ulid);
+        assert_eq!(
+            Ulid(u128::MAX).to_string().parse::<Ulid>()?,
+            Ulid(u128::MAX)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parses_lowercase_ulids() -> Result<()> {
+        let ulid: Ulid = "0000000000000000000000000z".parse()?;
+        assert_eq!(ulid, Ulid(31));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed_ulids() {
+        assert!("".parse::<Ulid>().is_err());
+        assert!("0000000000000000000000000".parse::<Ulid>().is_err());
+        assert!("0000000000000000000000000U".parse::<Ulid>().is_err());
+        assert!("8ZZZZZZZZZZZZZZZZZZZZZZZZZ".parse::<Ulid>().is_err());
+    }
+
+    #[test]
+    fn sorts_ulids_by_timestamp() {
+        let earlier = Ulid(1 << 80 | u128::from(u64::MAX));
+        let later = Ulid(2 << 80);
+        assert_eq!(earlier.timestamp_ms(), 1);
+        assert_eq!(later.timestamp_ms(), 2);
+        assert!(earlier.to_string() < later.to_string());
+    }
+
+    #[test]
+    fn stores_string_keys_as_strings() -> Result<()> {
+        let key = "user:alice".to_owned();
+        assert_eq!(String::from_bson(&key.to_bson())?, key);
+        assert!(String::from_bson(&Bson::Int32(1)).is_err());
+        Ok(())
+    }
+}
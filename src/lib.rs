@@ -1,6 +1,9 @@
 mod transaction;
 use transaction::*;
 
+mod session;
+use session::*;
+
 mod database;
 pub use database::*;
 
@@ -13,12 +16,30 @@ pub use context::*;
 mod id;
 pub use id::*;
 
+mod key;
+pub use key::*;
+
+mod codec;
+pub use codec::*;
+
+mod registry;
+pub use registry::*;
+
 mod object;
 pub use object::*;
 
 mod entity;
 pub use entity::*;
 
+mod count;
+pub use count::*;
+
+mod pipeline;
+pub use pipeline::*;
+
+mod bulk;
+pub use bulk::*;
+
 mod comparison;
 pub use comparison::*;
 
@@ -34,12 +55,33 @@ pub use updateable::*;
 mod discardable;
 pub use discardable::*;
 
+mod purge;
+pub use purge::*;
+
+mod dependents;
+pub use dependents::*;
+
+mod audit;
+pub use audit::*;
+
+mod policy;
+pub use policy::*;
+
+mod encryption;
+pub use encryption::*;
+
+mod outbox;
+pub use outbox::*;
+
+mod watch;
+pub use watch::*;
+
 use std::convert::TryFrom;
 use std::fmt::Result as FmtResult;
 use std::fmt::{Debug, Display, Formatter};
-use std::iter::FromIterator;
+use std::iter::{once, FromIterator};
 use std::marker::PhantomData;
-use std::ops::Deref;
+use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -51,7 +93,6 @@ use anyhow::Context as AnyhowContext;
 use anyhow::{Error, Result};
 
 use futures::{Future, Stream};
-use futures_util::future::try_join_all;
 use futures_util::future::BoxFuture;
 use futures_util::pin_mut;
 use futures_util::{FutureExt, StreamExt};
@@ -72,7 +113,7 @@ use async_trait::async_trait;
 use derivative::Derivative;
 use pin_project::pin_project;
 use tokio::sync::Mutex;
-use tracing::{trace, warn};
+use tracing::{error, trace, warn};
 use typed_builder::TypedBuilder as Builder;
 
 use chrono::DateTime as ChronoDateTime;
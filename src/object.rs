@@ -1,6 +1,131 @@
 use super::*;
 
+use serde::de::Visitor;
+use serde::forward_to_deserialize_any;
+
+use std::error::Error as StdError;
+
 pub trait Object: Sized {
     fn to_document(&self) -> Result<Document>;
     fn from_document(doc: Document) -> Result<Self>;
+
+    /// The fields of an entity's documents that this object is deserialized
+    /// from, which are the only ones loaded when it is selected (i.e. with
+    /// [`FindQuery::select`]).
+    ///
+    /// Defaults to the fields of the struct that `Self` deserializes as (see
+    /// [`projection_of`]), which should be overridden if it is converted from
+    /// a document type with other field names.
+    fn projection() -> Option<Document>
+    where
+        Self: DeserializeOwned,
+    {
+        projection_of::<Self>()
+    }
+}
+
+/// A projection of the fields of the struct that `D` deserializes as, which
+/// is `None` (i.e. all fields) if they can't be determined, such as for
+/// structs with flattened fields.
+pub fn projection_of<D: DeserializeOwned>() -> Option<Document> {
+    let mut fields = None;
+    let deserializer = FieldsDeserializer {
+        fields: &mut fields,
+    };
+    let _ = D::deserialize(deserializer);
+    let fields = fields?;
+    let projection = fields
+        .iter()
+        .map(|&field| (field.to_owned(), Bson::Int32(1)))
+        .collect();
+    Some(projection)
+}
+
+/// A deserializer that records the fields of the struct it is asked for,
+/// and deserializes nothing.
+struct FieldsDeserializer<'a> {
+    fields: &'a mut Option<&'static [&'static str]>,
+}
+
+impl<'de, 'a> Deserializer<'de> for FieldsDeserializer<'a> {
+    type Error = FieldsError;
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        Err(FieldsError)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.fields = Some(fields);
+        Err(FieldsError)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+#[derive(Debug)]
+struct FieldsError;
+
+impl Display for FieldsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str("not a struct")
+    }
+}
+
+impl StdError for FieldsError {}
+
+impl DeserializeError for FieldsError {
+    fn custom<T: Display>(_: T) -> Self {
+        FieldsError
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    #[allow(dead_code)]
+    struct Summary {
+        #[serde(rename = "_id")]
+        id: String,
+        display_name: String,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Flattened {
+        #[serde(flatten)]
+        summary: Summary,
+    }
+
+    #[test]
+    fn projects_struct_fields() {
+        let projection = projection_of::<Summary>();
+        let expected = doc! { "_id": 1, "displayName": 1 };
+        assert_eq!(projection, Some(expected));
+    }
+
+    #[test]
+    fn projects_everything_for_flattened_structs() {
+        assert_eq!(projection_of::<Flattened>(), None);
+    }
+
+    #[test]
+    fn projects_everything_for_other_types() {
+        assert_eq!(projection_of::<String>(), None);
+        assert_eq!(projection_of::<Document>(), None);
+    }
 }
@@ -0,0 +1,262 @@
+use super::*;
+
+use bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use bson::DateTime as BsonDateTime;
+
+use mongodb::error::Error as DatabaseError;
+use mongodb::options::FindOptions;
+
+use std::time::Duration;
+use tokio::time::sleep;
+
+const OUTBOX_COLLECTION_NAME: &str = "outboxEvent";
+
+fn outbox_collection<S: EntityServices>(
+    ctx: &EntityContext<S>,
+) -> Collection<Document> {
+    ctx.database().collection(OUTBOX_COLLECTION_NAME)
+}
+
+/// A domain event that can be published through the outbox.
+pub trait OutboxEvent: Object {
+    const TOPIC: &'static str;
+}
+
+/// An event stored in the outbox, as handed to an [`OutboxSink`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxMessage {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub topic: String,
+    pub payload: Document,
+
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime,
+
+    pub attempts: u32,
+}
+
+impl OutboxMessage {
+    pub fn decode<E: OutboxEvent>(&self) -> Result<E> {
+        if self.topic != E::TOPIC {
+            bail!("incorrect topic: expected {}, got {}", E::TOPIC, self.topic);
+        }
+        let payload = self.payload.clone();
+        E::from_document(payload).context("failed to deserialize event")
+    }
+}
+
+impl<S: EntityServices> EntityContext<S> {
+    /// Writes `event` to the outbox as part of the current transaction (or a
+    /// new one), so that it is relayed by an [`OutboxProcessor`] if and only
+    /// if the transaction commits.
+    pub async fn publish<E: OutboxEvent>(&self, event: &E) -> Result<()> {
+        let payload =
+            event.to_document().context("failed to serialize event")?;
+        check_sealed(&payload)?;
+        self.with_transaction(|ctx, transaction| async move {
+            let collection = outbox_collection(&ctx);
+            let id = ObjectId::new();
+            let created_at = BsonDateTime::from_chrono(now());
+            let doc = doc! {
+                "_id": id,
+                "topic": E::TOPIC,
+                "payload": payload,
+                "createdAt": created_at,
+                "attempts": 0,
+                "nextAttemptAt": created_at,
+            };
+
+            let mut transaction = transaction.lock().await;
+            transaction.track(&collection, id).await?;
+            let Transaction { session, .. } = &mut *transaction;
+
+            trace!(
+                collection = collection.name(),
+                %id,
+                topic = E::TOPIC,
+                "publishing event"
+            );
+            collection
+                .insert_one_with_session(doc, None, session)
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// A destination for events relayed out of the outbox.
+#[async_trait]
+pub trait OutboxSink: Send + Sync {
+    async fn deliver(&self, message: OutboxMessage) -> Result<()>;
+}
+
+#[async_trait]
+impl<F, U> OutboxSink for F
+where
+    F: Fn(OutboxMessage) -> U,
+    F: Send + Sync,
+    U: Future<Output = Result<()>> + Send,
+{
+    async fn deliver(&self, message: OutboxMessage) -> Result<()> {
+        self(message).await
+    }
+}
+
+/// Relays undelivered outbox events to an [`OutboxSink`].
+///
+/// Delivery is at-least-once: an event is only marked as delivered after the
+/// sink accepts it, so a crash in between (or several processors running
+/// against the same outbox) can deliver it again. Failed deliveries are
+/// retried with exponential backoff.
+///
+/// Events are stored in the database of the tenant they were published for
+/// (see [`EntityContext::with_tenant`]), and a processor only relays the
+/// outbox of its context's tenant; run one processor per tenant.
+#[derive(Derivative, Builder)]
+#[derivative(Debug(bound = ""))]
+pub struct OutboxProcessor<S: EntityServices, K: OutboxSink> {
+    #[derivative(Debug = "ignore")]
+    ctx: EntityContext<S>,
+
+    #[derivative(Debug = "ignore")]
+    sink: K,
+
+    #[builder(default = 100)]
+    batch_size: u32,
+
+    #[builder(default = Duration::from_secs(1))]
+    poll_interval: Duration,
+
+    #[builder(default = Duration::from_secs(1))]
+    min_backoff: Duration,
+
+    #[builder(default = Duration::from_secs(60 * 60))]
+    max_backoff: Duration,
+}
+
+impl<S: EntityServices, K: OutboxSink> OutboxProcessor<S, K> {
+    /// Relays events until an unrecoverable error occurs (or the returned
+    /// future is dropped), waiting for the poll interval whenever the outbox
+    /// has nothing due for delivery.
+    ///
+    /// Database errors are logged and retried with exponential backoff.
+    pub async fn run(&self) -> Result<()> {
+        let mut failures = 0;
+        loop {
+            match self.process().await {
+                Ok(processed) => {
+                    failures = 0;
+                    if processed == 0 {
+                        sleep(self.poll_interval).await;
+                    }
+                }
+                Err(error) if is_database_error(&error) => {
+                    let backoff = self.backoff(failures);
+                    failures += 1;
+                    error!(
+                        error = ?error,
+                        failures,
+                        "failed to process outbox; retrying in {:?}",
+                        backoff
+                    );
+                    sleep(backoff).await;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Relays a single batch of due events, returning how many were
+    /// attempted.
+    pub async fn process(&self) -> Result<usize> {
+        let Self {
+            ctx,
+            sink,
+            batch_size,
+            ..
+        } = self;
+        let collection = outbox_collection(ctx);
+
+        let conditions = doc! {
+            "deliveredAt": { "$exists": false },
+            "nextAttemptAt": { "$lte": BsonDateTime::from_chrono(now()) },
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "createdAt": 1 })
+            .limit(i64::from(*batch_size))
+            .build();
+        trace!(
+            collection = collection.name(),
+            %conditions,
+            "finding undelivered events"
+        );
+        let mut cursor = collection.find(conditions, options).await?;
+
+        let mut processed = 0;
+        while let Some(doc) = cursor.next().await {
+            let message: OutboxMessage = bson::from_document(doc?)
+                .context("failed to deserialize outbox message")?;
+            let OutboxMessage { id, attempts, .. } = message;
+            let conditions = doc! { "_id": id };
+
+            let update = match sink.deliver(message).await {
+                Ok(()) => {
+                    trace!(%id, "delivered event");
+                    doc! {
+                        "$set": {
+                            "deliveredAt": BsonDateTime::from_chrono(now()),
+                        },
+                        "$inc": { "attempts": 1 },
+                    }
+                }
+                Err(error) => {
+                    let backoff = self.backoff(attempts);
+                    warn!(
+                        %id,
+                        attempts = attempts + 1,
+                        error = ?error,
+                        "failed to deliver event; retrying in {:?}",
+                        backoff
+                    );
+                    let next_attempt_at = now()
+                        + chrono::Duration::from_std(backoff)
+                            .context("backoff out of range")?;
+                    doc! {
+                        "$set": {
+                            "nextAttemptAt":
+                                BsonDateTime::from_chrono(next_attempt_at),
+                            "lastError": format!("{:#}", error),
+                        },
+                        "$inc": { "attempts": 1 },
+                    }
+                }
+            };
+            collection
+                .update_one(conditions, update, None)
+                .await
+                .context("failed to update outbox message")?;
+            processed += 1;
+        }
+        Ok(processed)
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        let Self {
+            min_backoff,
+            max_backoff,
+            ..
+        } = self;
+        let factor = 2u32.saturating_pow(attempts);
+        min_backoff
+            .checked_mul(factor)
+            .map_or(*max_backoff, |backoff| backoff.min(*max_backoff))
+    }
+}
+
+fn is_database_error(error: &Error) -> bool {
+    error.chain().any(|error| error.is::<DatabaseError>())
+}
@@ -0,0 +1,183 @@
+use super::*;
+
+/// A builder for the stages of an aggregation pipeline over the collection
+/// of `T`, to be passed to [`Entity::aggregate`] (or
+/// [`AggregateQuery::new`]).
+///
+/// Typed stages (i.e. [`Pipeline::match_`]) refer to the fields of `T`, so
+/// they should come before the stages that reshape documents.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""), Clone(bound = ""), Default(bound = ""))]
+pub struct Pipeline<T: Entity> {
+    stages: Vec<Document>,
+    phantom: PhantomData<T>,
+}
+
+impl<T: Entity> Pipeline<T> {
+    pub fn new() -> Self {
+        default()
+    }
+
+    /// Appends a raw `stage`.
+    pub fn stage(mut self, stage: Document) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    /// Appends the stages of `pipeline`.
+    pub fn chain(mut self, pipeline: Pipeline<T>) -> Self {
+        self.stages.extend(pipeline.stages);
+        self
+    }
+
+    pub fn match_(self, conditions: T::Conditions) -> Self {
+        let conditions = conditions.to_document();
+        self.stage(doc! { "$match": conditions })
+    }
+
+    pub fn sort(self, sorting: T::Sorting) -> Self {
+        let sorting = sorting.to_document();
+        // An empty $sort stage is invalid.
+        if sorting.is_empty() {
+            return self;
+        }
+        self.stage(doc! { "$sort": sorting })
+    }
+
+    /// Groups documents by `id` (i.e. "$field"), computing `fields` with
+    /// accumulators for each group.
+    pub fn group(self, id: impl Into<Bson>, fields: Document) -> Self {
+        let mut group = doc! { "_id": id.into() };
+        group.extend(fields);
+        self.stage(doc! { "$group": group })
+    }
+
+    pub fn project(self, projection: Document) -> Self {
+        self.stage(doc! { "$project": projection })
+    }
+
+    /// Joins the entities of type `U` whose `foreign_field` equals the
+    /// `local_field` of each document, into an array at `field`.
+    ///
+    /// Only the entities of `U` that are visible through `ctx` are joined,
+    /// per its default scope and [`Entity::read_filter`].
+    pub fn lookup<U>(
+        self,
+        ctx: &EntityContext<T::Services>,
+        local_field: &str,
+        foreign_field: &str,
+        field: &str,
+    ) -> Self
+    where
+        U: Entity<Services = T::Services>,
+    {
+        let local = format!("${}", local_field);
+        let foreign = format!("${}", foreign_field);
+        let mut pipeline = vec![doc! {
+            "$match": { "$expr": { "$eq": [foreign, "$$local"] } }
+        }];
+        if let Some(scope) = embedded_scope_conditions::<U>(ctx) {
+            pipeline.push(doc! { "$match": scope });
+        }
+        self.stage(doc! {
+            "$lookup": {
+                "from": U::collection_name(),
+                "let": { "local": local },
+                "pipeline": pipeline,
+                "as": field,
+            }
+        })
+    }
+
+    /// Outputs a document for each element of the array at `field`.
+    pub fn unwind(self, field: &str) -> Self {
+        let path = format!("${}", field);
+        self.stage(doc! { "$unwind": path })
+    }
+
+    /// Runs each of `pipelines` over the same input documents, outputting
+    /// their results in a single document under their names.
+    pub fn facet<I, K>(self, pipelines: I) -> Self
+    where
+        I: IntoIterator<Item = (K, Pipeline<T>)>,
+        K: Into<String>,
+    {
+        let facets = pipelines
+            .into_iter()
+            .map(|(name, pipeline)| {
+                let stages = pipeline.stages.into_iter().map(Bson::from);
+                (name.into(), Bson::Array(stages.collect()))
+            })
+            .collect::<Document>();
+        self.stage(doc! { "$facet": facets })
+    }
+
+    pub fn bucket(self, bucket: Bucket) -> Self {
+        let Bucket {
+            group_by,
+            boundaries,
+            default,
+            output,
+        } = bucket;
+        let mut bucket = doc! {
+            "groupBy": group_by,
+            "boundaries": boundaries,
+        };
+        if let Some(default) = default {
+            bucket.insert("default", default);
+        }
+        if let Some(output) = output {
+            bucket.insert("output", output);
+        }
+        self.stage(doc! { "$bucket": bucket })
+    }
+
+    pub fn add_fields(self, fields: Document) -> Self {
+        self.stage(doc! { "$addFields": fields })
+    }
+
+    /// Outputs a single document with the number of input documents at
+    /// `field`.
+    pub fn count(self, field: &str) -> Self {
+        self.stage(doc! { "$count": field })
+    }
+
+    pub fn into_stages(self) -> Vec<Document> {
+        self.stages
+    }
+}
+
+impl<T: Entity> IntoIterator for Pipeline<T> {
+    type Item = Document;
+    type IntoIter = std::vec::IntoIter<Document>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.stages.into_iter()
+    }
+}
+
+impl<T: Entity> From<Pipeline<T>> for Vec<Document> {
+    fn from(pipeline: Pipeline<T>) -> Self {
+        pipeline.into_stages()
+    }
+}
+
+/// A `$bucket` stage, which groups documents into buckets by the value of
+/// `group_by` (i.e. "$field"), between consecutive `boundaries`.
+#[derive(Debug, Clone, Builder)]
+pub struct Bucket {
+    #[builder(setter(into))]
+    pub group_by: Bson,
+
+    pub boundaries: Vec<Bson>,
+
+    /// The bucket for documents outside of the boundaries, which are
+    /// otherwise rejected.
+    #[builder(default, setter(into, strip_option))]
+    pub default: Option<Bson>,
+
+    /// Fields computed with accumulators for each bucket, instead of a
+    /// `count`.
+    #[builder(default, setter(strip_option))]
+    pub output: Option<Document>,
+}
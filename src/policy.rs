@@ -0,0 +1,112 @@
+use super::*;
+
+use std::error::Error as StdError;
+
+/// The error returned when an entity doesn't exist, or isn't visible through
+/// the context it was loaded with.
+#[derive(Debug, Clone)]
+pub struct NotFoundError {
+    pub entity: &'static str,
+}
+
+impl NotFoundError {
+    pub(super) fn new<T: Entity>() -> Self {
+        Self { entity: T::NAME }
+    }
+}
+
+impl Display for NotFoundError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} not found", self.entity)
+    }
+}
+
+impl StdError for NotFoundError {}
+
+/// The error returned when [`Entity::can_write`] refuses a write.
+#[derive(Debug, Clone)]
+pub struct ForbiddenError {
+    pub entity: &'static str,
+    pub id: String,
+    pub actor: Option<String>,
+    pub action: WriteAction,
+}
+
+impl ForbiddenError {
+    pub(super) fn new<T: Entity>(
+        ctx: &EntityContext<T::Services>,
+        entity: &T,
+        action: WriteAction,
+    ) -> Self {
+        Self {
+            entity: T::NAME,
+            id: entity.id().to_string(),
+            actor: ctx.actor().map(ToOwned::to_owned),
+            action,
+        }
+    }
+}
+
+impl Display for ForbiddenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Self {
+            entity,
+            id,
+            actor,
+            action,
+        } = self;
+        let actor = actor.as_deref().unwrap_or("anonymous actor");
+        write!(f, "{} may not {} {} {}", actor, action, entity, id)
+    }
+}
+
+impl StdError for ForbiddenError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteAction {
+    Save,
+    Delete,
+}
+
+impl Display for WriteAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let action = match self {
+            WriteAction::Save => "save",
+            WriteAction::Delete => "delete",
+        };
+        f.write_str(action)
+    }
+}
+
+/// Fails with a [`ForbiddenError`] unless `entity` may be written through
+/// `ctx`.
+pub(super) fn authorize_write<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+    entity: &T,
+    action: WriteAction,
+) -> Result<()> {
+    if !entity.can_write(ctx, action) {
+        let error = ForbiddenError::new(ctx, entity, action);
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+/// Fails with a [`ForbiddenError`] unless the stored document `prior`, which
+/// a write through `ctx` would overwrite or remove, may be both read and
+/// written with `action`; the entity being written isn't enough to go by,
+/// since it may have been given the id of another.
+pub(super) fn authorize_prior<T: Entity>(
+    ctx: &EntityContext<T::Services>,
+    prior: &Document,
+    action: WriteAction,
+) -> Result<()> {
+    let entity = ctx
+        .with_encryption(|| T::from_document(prior.to_owned()))
+        .context("failed to deserialize entity")?;
+    if !entity.can_read(ctx) || !entity.can_write(ctx, action) {
+        let error = ForbiddenError::new(ctx, &entity, action);
+        return Err(error.into());
+    }
+    Ok(())
+}
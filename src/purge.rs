@@ -0,0 +1,130 @@
+use super::*;
+
+use std::time::Duration;
+use tokio::time::sleep;
+
+pub(super) const PURGE_BATCH_SIZE: u64 = 100;
+
+/// Finds entities that were discarded before `before`, bypassing the
+/// discarder of the default scope.
+fn discarded_before<T: Discardable>(before: &DateTime) -> Result<FindQuery<T>> {
+    let path = T::discarded_at_path();
+    let before = T::discarded_at_to_bson(before)?;
+    let conditions = doc! { path: { "$lt": before } };
+    let query = FindQuery::new_untyped(conditions).with_discarded();
+    Ok(query)
+}
+
+/// Deletes entities that were discarded before `before`, `batch_size` at a
+/// time, running the delete callbacks of each.
+pub(super) async fn purge_discarded<T: Discardable>(
+    ctx: &EntityContext<T::Services>,
+    before: &DateTime,
+    batch_size: u64,
+) -> Result<u64> {
+    validate_batch_size(batch_size)?;
+    let mut purged = 0;
+    loop {
+        let query = discarded_before::<T>(before)?.take(batch_size);
+        let deleted = query.delete_all(ctx).await?;
+        purged += deleted;
+        if deleted < batch_size {
+            break;
+        }
+    }
+    Ok(purged)
+}
+
+/// Fails unless `batch_size` is at least 1; a batch size of 0 would mean no
+/// limit to `take`, and purging would never end.
+fn validate_batch_size(batch_size: u64) -> Result<()> {
+    if batch_size == 0 {
+        bail!("purge batch size must be at least 1");
+    }
+    Ok(())
+}
+
+/// The outcome of a single [`DiscardPurger`] run.
+#[derive(Debug, Clone)]
+pub struct PurgeReport {
+    /// Entities discarded before this time were purged.
+    pub cutoff: DateTime,
+
+    /// The number of entities purged (or, in a dry run, that would have been
+    /// purged).
+    pub count: u64,
+
+    pub dry_run: bool,
+}
+
+/// Periodically hard-deletes entities that have been discarded for longer
+/// than a retention period.
+///
+/// Only the entities of its context's tenant are purged (see
+/// [`EntityContext::with_tenant`]); run one purger per tenant.
+#[derive(Derivative, Builder)]
+#[derivative(Debug(bound = ""))]
+pub struct DiscardPurger<T: Discardable> {
+    #[derivative(Debug = "ignore")]
+    ctx: EntityContext<T::Services>,
+
+    retention: Duration,
+
+    /// The number of entities deleted at a time, which must be at least 1.
+    #[builder(default = PURGE_BATCH_SIZE)]
+    batch_size: u64,
+
+    #[builder(default = Duration::from_secs(60 * 60))]
+    interval: Duration,
+
+    /// Whether to only count the entities that would be purged, without
+    /// deleting them.
+    #[builder(default)]
+    dry_run: bool,
+}
+
+impl<T: Discardable> DiscardPurger<T> {
+    /// Purges entities until an error occurs, waiting for the interval
+    /// between runs.
+    pub async fn run(&self) -> Result<()> {
+        loop {
+            self.purge().await?;
+            sleep(self.interval).await;
+        }
+    }
+
+    /// Purges entities that were discarded before the retention period.
+    pub async fn purge(&self) -> Result<PurgeReport> {
+        let Self {
+            ctx,
+            retention,
+            batch_size,
+            dry_run,
+            ..
+        } = self;
+        validate_batch_size(*batch_size)?;
+        let cutoff = now()
+            - chrono::Duration::from_std(*retention)
+                .context("retention out of range")?;
+
+        let count = if *dry_run {
+            discarded_before::<T>(&cutoff)?.count(ctx).await?
+        } else {
+            purge_discarded::<T>(ctx, &cutoff, *batch_size).await?
+        };
+        trace!(
+            collection = %T::collection_name(),
+            %cutoff,
+            count,
+            dry_run,
+            "purged discarded entities"
+        );
+
+        let report = PurgeReport {
+            cutoff,
+            count,
+            dry_run: *dry_run,
+        };
+        Ok(report)
+    }
+}
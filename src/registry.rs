@@ -0,0 +1,299 @@
+use super::*;
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+
+/// An id of an entity of any type, in the global form of [`IdCodec::Global`]
+/// (or [`IdCodec::Signed`], for entities whose ids are signed).
+///
+/// Ids of entities that use other codecs can be converted from their
+/// [`EntityId`] with `AnyEntityId::from`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AnyEntityId {
+    entity: String,
+    key: String,
+    raw: String,
+}
+
+impl AnyEntityId {
+    /// The name of the entity type.
+    pub fn entity(&self) -> &str {
+        &self.entity
+    }
+
+    /// The key, as formatted by its [`EntityKey`].
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn typed<T: Entity>(&self) -> Result<EntityId<T>> {
+        T::id_codec().decode_global(&self.raw)
+    }
+}
+
+impl<T: Entity> From<EntityId<T>> for AnyEntityId {
+    fn from(id: EntityId<T>) -> Self {
+        Self {
+            entity: T::NAME.to_owned(),
+            key: id.key().to_string(),
+            raw: T::id_codec().encode_global(&id),
+        }
+    }
+}
+
+impl Display for AnyEntityId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(&self.raw)
+    }
+}
+
+impl FromStr for AnyEntityId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Signatures are verified once the entity type is known.
+        let (id, _) = s.rsplit_once('.').unwrap_or((s, ""));
+        let id = {
+            let data = decode_base64(id).context("failed to decode base64")?;
+            String::from_utf8_lossy(&data[..]).into_owned()
+        };
+        let (entity, key) = match id.split_once(':') {
+            Some((entity, key)) => (entity.to_owned(), key.to_owned()),
+            None => bail!("bad format"),
+        };
+        let id = Self {
+            entity,
+            key,
+            raw: s.to_owned(),
+        };
+        Ok(id)
+    }
+}
+
+impl Serialize for AnyEntityId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.raw.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AnyEntityId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|error| {
+            let message = format!("{:?}", error);
+            D::Error::custom(message)
+        })
+    }
+}
+
+/// An entity of any type, as loaded by an [`EntityRegistry`].
+pub trait AnyEntity: Send + Sync + 'static {
+    fn entity_name(&self) -> &'static str;
+    fn any_id(&self) -> AnyEntityId;
+
+    fn as_any(&self) -> &dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send + Sync>;
+}
+
+impl<T: Entity> AnyEntity for T {
+    fn entity_name(&self) -> &'static str {
+        T::NAME
+    }
+
+    fn any_id(&self) -> AnyEntityId {
+        self.id().into()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl dyn AnyEntity {
+    pub fn is<T: Entity>(&self) -> bool {
+        self.as_any().is::<T>()
+    }
+
+    pub fn downcast_ref<T: Entity>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+
+    pub fn downcast<T: Entity>(self: Box<Self>) -> Result<T, Box<Self>> {
+        if self.is::<T>() {
+            let entity = self.into_any().downcast::<T>().unwrap();
+            Ok(*entity)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl Debug for dyn AnyEntity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let id = self.any_id();
+        write!(f, "AnyEntity({}:{})", id.entity(), id.key())
+    }
+}
+
+impl<T: Entity> From<T> for Box<dyn AnyEntity> {
+    fn from(entity: T) -> Self {
+        Box::new(entity)
+    }
+}
+
+type EntityLoader<S, R> = for<'a> fn(
+    &'a EntityContext<S>,
+    &'a AnyEntityId,
+) -> BoxFuture<'a, Result<Option<R>>>;
+
+/// Loads entities of the registered types by their [`AnyEntityId`], i.e. to
+/// resolve GraphQL `node(id:)` lookups.
+///
+/// Entities are loaded as an `R`, which is either a `Box<dyn AnyEntity>`
+/// (the default) or any type that can be converted from each registered
+/// entity, such as an enum with a variant for each.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct EntityRegistry<S: EntityServices, R = Box<dyn AnyEntity>> {
+    loaders: HashMap<&'static str, EntityLoader<S, R>>,
+}
+
+impl<S: EntityServices, R> EntityRegistry<S, R> {
+    pub fn new() -> Self {
+        Self { loaders: default() }
+    }
+
+    /// The names of the registered entity types.
+    pub fn entities(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.loaders.keys().copied()
+    }
+}
+
+impl<S: EntityServices, R: Send + 'static> EntityRegistry<S, R> {
+    /// Registers the entity type `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an entity type with the same name is already registered.
+    pub fn register<T>(self) -> Self
+    where
+        T: Entity<Services = S>,
+        R: From<T>,
+    {
+        match self.try_register::<T>() {
+            Ok(registry) => registry,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /// Registers the entity type `T`, which fails with a
+    /// [`DuplicateEntityError`] if an entity type with the same name is
+    /// already registered.
+    pub fn try_register<T>(mut self) -> Result<Self, DuplicateEntityError>
+    where
+        T: Entity<Services = S>,
+        R: From<T>,
+    {
+        if self.loaders.contains_key(T::NAME) {
+            let error = DuplicateEntityError { entity: T::NAME };
+            return Err(error);
+        }
+        let loader: EntityLoader<S, R> = load_entity::<T, R>;
+        self.loaders.insert(T::NAME, loader);
+        Ok(self)
+    }
+
+    /// Loads the entity with `id`, which fails with an [`UnknownEntityError`]
+    /// if its type isn't registered.
+    pub async fn load(
+        &self,
+        ctx: &EntityContext<S>,
+        id: &AnyEntityId,
+    ) -> Result<Option<R>> {
+        let loader = match self.loaders.get(id.entity()) {
+            Some(loader) => loader,
+            None => {
+                let error = UnknownEntityError {
+                    entity: id.entity().to_owned(),
+                };
+                return Err(error.into());
+            }
+        };
+        loader(ctx, id).await
+    }
+}
+
+impl<S: EntityServices, R> Default for EntityRegistry<S, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: EntityServices, R> Debug for EntityRegistry<S, R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("EntityRegistry")
+            .field("entities", &self.loaders.keys())
+            .finish()
+    }
+}
+
+fn load_entity<'a, T, R>(
+    ctx: &'a EntityContext<T::Services>,
+    id: &'a AnyEntityId,
+) -> BoxFuture<'a, Result<Option<R>>>
+where
+    T: Entity,
+    R: From<T> + Send + 'static,
+{
+    async move {
+        let id = id.typed::<T>()?;
+        let entity = T::get(id).optional().load(ctx).await?;
+        Ok(entity.map(R::from))
+    }
+    .boxed()
+}
+
+/// The error returned when loading an entity whose type isn't registered
+/// with an [`EntityRegistry`].
+#[derive(Debug, Clone)]
+pub struct UnknownEntityError {
+    pub entity: String,
+}
+
+impl Display for UnknownEntityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "unknown entity: {}", self.entity)
+    }
+}
+
+impl StdError for UnknownEntityError {}
+
+/// The error returned when registering an entity type with an
+/// [`EntityRegistry`] that already has one with the same name.
+#[derive(Debug, Clone)]
+pub struct DuplicateEntityError {
+    pub entity: &'static str,
+}
+
+impl Display for DuplicateEntityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "entity {} is already registered", self.entity)
+    }
+}
+
+impl StdError for DuplicateEntityError {}
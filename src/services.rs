@@ -7,12 +7,36 @@ where
 {
     fn database(&self) -> &Database;
     fn database_client(&self) -> &DatabaseClient;
+
+    /// The database that holds the entities of `tenant`.
+    ///
+    /// Defaults to a database on the same client, named after
+    /// [`EntityServices::database`] and `tenant` (i.e. "app_acme"). Tenants
+    /// whose database name would be invalid are rejected by
+    /// [`EntityContext::with_tenant`].
+    fn tenant_database(&self, tenant: &str) -> Database {
+        let name = format!("{}_{}", self.database().name(), tenant);
+        self.database_client().database(&name)
+    }
+
+    /// The keys used to encrypt and decrypt [`Encrypted`] values.
+    fn key_provider(&self) -> Option<Arc<dyn KeyProvider>> {
+        None
+    }
+
+    /// Called with the error of each commit or abort finalizer that fails.
+    fn on_finalizer_error(&self, error: Error) {
+        error!(error = ?error, "transaction finalizer failed");
+    }
 }
 
 #[derive(Debug, Clone, Builder)]
 pub struct Services {
     database: Database,
     database_client: DatabaseClient,
+
+    #[builder(default, setter(strip_option))]
+    key_provider: Option<Arc<dyn KeyProvider>>,
 }
 
 impl EntityServices for Services {
@@ -23,4 +47,8 @@ impl EntityServices for Services {
     fn database_client(&self) -> &DatabaseClient {
         &self.database_client
     }
+
+    fn key_provider(&self) -> Option<Arc<dyn KeyProvider>> {
+        self.key_provider.clone()
+    }
 }
@@ -0,0 +1,113 @@
+use super::*;
+
+use mongodb::error::Result as DatabaseResult;
+use mongodb::SessionCursor;
+
+use tokio::sync::MutexGuard;
+
+#[derive(Debug, Clone)]
+pub(super) enum SessionHandle {
+    Transaction(Arc<Mutex<Transaction>>),
+    Causal(Arc<Mutex<DatabaseSession>>),
+}
+
+impl SessionHandle {
+    pub async fn lock(&self) -> SessionGuard<'_> {
+        use SessionHandle::*;
+        match self {
+            Transaction(transaction) => {
+                let transaction = transaction.lock().await;
+                SessionGuard::Transaction(transaction)
+            }
+            Causal(session) => {
+                let session = session.lock().await;
+                SessionGuard::Causal(session)
+            }
+        }
+    }
+}
+
+pub(super) enum SessionGuard<'a> {
+    Transaction(MutexGuard<'a, Transaction>),
+    Causal(MutexGuard<'a, DatabaseSession>),
+}
+
+impl Deref for SessionGuard<'_> {
+    type Target = DatabaseSession;
+
+    fn deref(&self) -> &Self::Target {
+        use SessionGuard::*;
+        match self {
+            Transaction(transaction) => &transaction.session,
+            Causal(session) => session,
+        }
+    }
+}
+
+impl DerefMut for SessionGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        use SessionGuard::*;
+        match self {
+            Transaction(transaction) => &mut transaction.session,
+            Causal(session) => session,
+        }
+    }
+}
+
+/// Advances `session` to the cluster and operation times observed by `from`,
+/// so that its subsequent reads observe every write `from` has seen.
+pub(super) fn advance_session(
+    session: &mut DatabaseSession,
+    from: &DatabaseSession,
+) {
+    if let Some(cluster_time) = from.cluster_time() {
+        session.advance_cluster_time(cluster_time);
+    }
+    if let Some(operation_time) = from.operation_time() {
+        session.advance_operation_time(operation_time);
+    }
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub(super) struct SessionStream<T>
+where
+    T: DeserializeOwned + Unpin,
+    T: Send + Sync,
+{
+    cursor: SessionCursor<T>,
+    session: SessionHandle,
+}
+
+impl<T> SessionStream<T>
+where
+    T: DeserializeOwned + Unpin,
+    T: Send + Sync,
+{
+    pub fn new(cursor: SessionCursor<T>, session: SessionHandle) -> Self {
+        Self { cursor, session }
+    }
+
+    async fn next(self: Pin<&mut Self>) -> Option<DatabaseResult<T>> {
+        let projection = self.project();
+        let mut session = projection.session.lock().await;
+        projection.cursor.next(&mut session).await
+    }
+}
+
+impl<T> Stream for SessionStream<T>
+where
+    T: DeserializeOwned + Unpin,
+    T: Send + Sync,
+{
+    type Item = DatabaseResult<T>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> TaskPoll<Option<Self::Item>> {
+        let future = self.next();
+        pin_mut!(future);
+        future.poll(cx)
+    }
+}
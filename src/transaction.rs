@@ -1,15 +1,28 @@
 use super::*;
 
+use mongodb::options::FindOptions;
+use mongodb::options::ReplaceOptions;
+
+use std::collections::{HashMap, HashSet};
+use std::mem::take;
+
+pub(super) type Finalizer = BoxFuture<'static, Result<()>>;
+
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub(super) struct Transaction {
     pub session: DatabaseSession,
 
     #[derivative(Debug = "ignore")]
-    pub commit_finalizers: Vec<BoxFuture<'static, Result<()>>>,
+    pub commit_finalizers: Vec<Finalizer>,
 
     #[derivative(Debug = "ignore")]
-    pub abort_finalizers: Vec<BoxFuture<'static, Result<()>>>,
+    pub abort_finalizers: Vec<Finalizer>,
+
+    pub scopes: Vec<TransactionScope>,
+
+    /// The tenant of the context that began the transaction.
+    pub tenant: Option<String>,
 }
 
 impl Transaction {
@@ -26,31 +39,271 @@ impl Transaction {
             session,
             commit_finalizers: default(),
             abort_finalizers: default(),
+            scopes: default(),
+            tenant: None,
         };
         Ok(transaction)
     }
 
-    pub async fn commit(&mut self) -> Result<()> {
+    /// Commits the transaction, handing back its commit finalizers so that
+    /// they can be run once the transaction is released.
+    pub async fn commit(&mut self) -> Result<Vec<Finalizer>> {
         let Transaction {
             session,
             commit_finalizers,
             ..
         } = self;
         session.commit_transaction().await?;
-        try_join_all(commit_finalizers).await?;
-        Ok(())
+        Ok(take(commit_finalizers))
     }
 
-    pub async fn abort(&mut self) -> Result<()> {
+    /// Aborts the transaction, handing back its abort finalizers so that
+    /// they can be run once the transaction is released.
+    pub async fn abort(&mut self) -> Result<Vec<Finalizer>> {
         let Transaction {
             session,
             abort_finalizers,
             ..
         } = self;
         session.abort_transaction().await?;
-        try_join_all(abort_finalizers).await?;
+        Ok(take(abort_finalizers))
+    }
+
+    /// Opens a savepoint scope, whose writes can later be compensated by
+    /// [`Transaction::rollback_scope`].
+    pub fn begin_scope(&mut self) {
+        let scope = TransactionScope {
+            commit_finalizers: self.commit_finalizers.len(),
+            abort_finalizers: self.abort_finalizers.len(),
+            writes: default(),
+            tracked: default(),
+        };
+        self.scopes.push(scope);
+    }
+
+    /// Closes the innermost savepoint scope, handing its writes over to the
+    /// enclosing scope (if any).
+    pub fn release_scope(&mut self) {
+        let scope = self.scopes.pop().expect("no savepoint scope to release");
+        if let Some(parent) = self.scopes.last_mut() {
+            for write in scope.writes {
+                parent.push(write);
+            }
+        }
+    }
+
+    /// Closes the innermost savepoint scope, restoring every document it
+    /// wrote to its prior state and dropping the finalizers it registered.
+    pub async fn rollback_scope(&mut self) -> Result<()> {
+        let TransactionScope {
+            commit_finalizers,
+            abort_finalizers,
+            writes,
+            ..
+        } = self.scopes.pop().expect("no savepoint scope to roll back");
+        self.commit_finalizers.truncate(commit_finalizers);
+        self.abort_finalizers.truncate(abort_finalizers);
+
+        let session = &mut self.session;
+        for write in writes.into_iter().rev() {
+            let TransactionWrite {
+                collection,
+                id,
+                prior,
+            } = write;
+            let conditions = doc! { "_id": &id };
+            match prior {
+                Some(prior) => {
+                    trace!(
+                        collection = collection.name(),
+                        %conditions,
+                        "restoring document"
+                    );
+                    let options =
+                        ReplaceOptions::builder().upsert(true).build();
+                    collection
+                        .replace_one_with_session(
+                            conditions, prior, options, session,
+                        )
+                        .await
+                        .context("failed to restore document")?;
+                }
+                None => {
+                    trace!(
+                        collection = collection.name(),
+                        %conditions,
+                        "removing document"
+                    );
+                    collection
+                        .delete_one_with_session(conditions, None, session)
+                        .await
+                        .context("failed to remove document")?;
+                }
+            }
+        }
         Ok(())
     }
+
+    /// Records the current state of the document with the given id, so that
+    /// it can be restored if the innermost savepoint scope is rolled back.
+    ///
+    /// Does nothing outside of a savepoint scope.
+    pub async fn track(
+        &mut self,
+        collection: &Collection<Document>,
+        id: impl Into<Bson>,
+    ) -> Result<()> {
+        self.track_many(collection, once(id.into())).await
+    }
+
+    /// Records that the document with the given id did not exist prior to
+    /// being written, so that it is removed if the innermost savepoint scope
+    /// is rolled back.
+    pub fn track_inserted(
+        &mut self,
+        collection: &Collection<Document>,
+        id: impl Into<Bson>,
+    ) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(TransactionWrite {
+                collection: collection.clone(),
+                id: id.into(),
+                prior: None,
+            });
+        }
+    }
+
+    /// Records `prior` as the state of the document with the given id before
+    /// it was written, as returned by the write itself.
+    pub fn track_prior(
+        &mut self,
+        collection: &Collection<Document>,
+        id: impl Into<Bson>,
+        prior: Document,
+    ) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(TransactionWrite {
+                collection: collection.clone(),
+                id: id.into(),
+                prior: Some(prior),
+            });
+        }
+    }
+
+    /// Like [`Transaction::track`], but for every document that matches
+    /// `conditions`. Returns the number of matching documents.
+    pub async fn track_matching(
+        &mut self,
+        collection: &Collection<Document>,
+        conditions: Document,
+    ) -> Result<usize> {
+        if self.scopes.is_empty() {
+            return Ok(0);
+        }
+
+        let mut ids = Vec::new();
+        {
+            let session = &mut self.session;
+            let options =
+                FindOptions::builder().projection(doc! { "_id": 1 }).build();
+            let mut cursor = collection
+                .find_with_session(conditions, options, session)
+                .await
+                .context("failed to find prior documents")?;
+            while let Some(doc) = cursor.next(session).await {
+                let doc = doc.context("failed to find prior document")?;
+                let id = doc.get("_id").context("missing document id")?;
+                ids.push(id.to_owned());
+            }
+        }
+        let matched = ids.len();
+        self.track_many(collection, ids).await?;
+        Ok(matched)
+    }
+
+    /// Like [`Transaction::track`], but for several documents at once.
+    pub async fn track_many(
+        &mut self,
+        collection: &Collection<Document>,
+        ids: impl IntoIterator<Item = Bson>,
+    ) -> Result<()> {
+        let Transaction {
+            session, scopes, ..
+        } = self;
+        let scope = match scopes.last_mut() {
+            Some(scope) => scope,
+            None => return Ok(()),
+        };
+
+        let ids = ids
+            .into_iter()
+            .filter(|id| !scope.has_tracked(collection, id))
+            .collect::<Vec<_>>();
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut priors = HashMap::new();
+        {
+            let conditions = doc! { "_id": { "$in": ids.clone() } };
+            let mut cursor = collection
+                .find_with_session(conditions, None, session)
+                .await
+                .context("failed to load prior documents")?;
+            while let Some(doc) = cursor.next(session).await {
+                let doc = doc.context("failed to load prior document")?;
+                let id = doc.get("_id").context("missing document id")?;
+                priors.insert(id.to_string(), doc);
+            }
+        }
+        for id in ids {
+            let prior = priors.remove(&id.to_string());
+            scope.push(TransactionWrite {
+                collection: collection.clone(),
+                id,
+                prior,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct TransactionScope {
+    commit_finalizers: usize,
+    abort_finalizers: usize,
+    writes: Vec<TransactionWrite>,
+    tracked: HashSet<String>,
+}
+
+impl TransactionScope {
+    fn has_tracked(
+        &self,
+        collection: &Collection<Document>,
+        id: &Bson,
+    ) -> bool {
+        let key = write_key(collection, id);
+        self.tracked.contains(&key)
+    }
+
+    fn push(&mut self, write: TransactionWrite) {
+        let key = write_key(&write.collection, &write.id);
+        if self.tracked.insert(key) {
+            self.writes.push(write);
+        }
+    }
+}
+
+fn write_key(collection: &Collection<Document>, id: &Bson) -> String {
+    let namespace = collection.namespace();
+    format!("{}.{}:{}", namespace.db, namespace.coll, id)
+}
+
+#[derive(Debug)]
+struct TransactionWrite {
+    collection: Collection<Document>,
+    id: Bson,
+    prior: Option<Document>,
 }
 
 #[derive(Debug)]
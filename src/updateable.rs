@@ -2,8 +2,8 @@ use super::*;
 
 #[async_trait]
 pub trait Updateable: Entity {
-    fn as_updateable(&self) -> UpdateableView;
-    fn as_updateable_mut(&mut self) -> UpdateableViewMut;
+    fn as_updateable(&self) -> UpdateableView<'_>;
+    fn as_updateable_mut(&mut self) -> UpdateableViewMut<'_>;
 
     async fn update(
         &mut self,
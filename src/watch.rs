@@ -0,0 +1,474 @@
+use super::*;
+
+use bson::DateTime as BsonDateTime;
+use bson::Timestamp;
+
+use mongodb::error::Error as DatabaseError;
+use mongodb::error::ErrorKind as DatabaseErrorKind;
+use mongodb::error::Result as DatabaseResult;
+use mongodb::options::UpdateOptions;
+use mongodb::Cursor;
+
+use futures_util::stream::unfold;
+
+const CHECKPOINT_COLLECTION_NAME: &str = "watchCheckpoint";
+
+/// The number of times in a row the stream is reopened after a transient
+/// error, before the error is returned.
+const MAX_RESUME_ATTEMPTS: u32 = 3;
+
+/// Server error codes after which a change stream can be resumed, for
+/// servers that don't label them as `ResumableChangeStreamError`.
+const RESUMABLE_ERROR_CODES: &[i32] = &[
+    6, 7, 63, 89, 91, 133, 150, 189, 234, 262, 9001, 10107, 11600, 11602,
+    13388, 13435, 13436,
+];
+
+/// A change made to an entity, as observed by [`WatchQuery`].
+#[derive(Debug, Clone)]
+pub enum EntityChange<T: Entity> {
+    Inserted(T),
+    Updated {
+        id: EntityId<T>,
+        fields: Document,
+        removed_fields: Vec<String>,
+    },
+    Replaced(T),
+    Deleted(EntityId<T>),
+}
+
+/// A subscription to changes made to entities of type `T`, backed by a
+/// MongoDB change stream.
+///
+/// When filtering by conditions, conditions are matched against the full
+/// document after each change; deletions are always reported, since the
+/// deleted document is no longer available to match against. Conditions
+/// may use `$expr`, but not operators that apply to the document as a whole
+/// (i.e. `$where`, `$jsonSchema` and `$text`).
+///
+/// The stream is reopened from the last change it delivered after transient
+/// errors (i.e. network errors and primary elections), or from when it was
+/// first opened if it hasn't delivered any.
+#[derive(Debug, Clone)]
+pub struct WatchQuery<T: Entity> {
+    conditions: Option<Document>,
+    checkpoint: Option<String>,
+    phantom: PhantomData<T>,
+}
+
+impl<T: Entity> WatchQuery<T> {
+    pub fn new(conditions: impl Into<Option<T::Conditions>>) -> Self {
+        let conditions: Option<_> = conditions.into();
+        Self {
+            conditions: conditions.as_ref().map(EntityConditions::to_document),
+            checkpoint: None,
+            phantom: default(),
+        }
+    }
+
+    /// Persists the position of the stream under `name`, so that a later
+    /// subscription with the same name resumes where this one left off.
+    ///
+    /// A change is checkpointed once the next change is requested, so changes
+    /// are delivered at least once across restarts.
+    pub fn checkpoint(mut self, name: impl Into<String>) -> Self {
+        self.checkpoint = Some(name.into());
+        self
+    }
+
+    pub async fn load(
+        self,
+        ctx: &EntityContext<T::Services>,
+    ) -> Result<impl Stream<Item = Result<EntityChange<T>>>> {
+        let Self {
+            conditions,
+            checkpoint,
+            ..
+        } = self;
+        let conditions = conditions
+            .map(|conditions| seal_conditions(ctx, conditions))
+            .transpose()?;
+        let collection = T::collection(ctx);
+        let checkpoints = ctx
+            .database()
+            .collection::<Document>(CHECKPOINT_COLLECTION_NAME);
+
+        let resume_token = match &checkpoint {
+            Some(name) => {
+                let doc = checkpoints
+                    .find_one(doc! { "_id": name }, None)
+                    .await
+                    .context("failed to load checkpoint")?;
+                match doc {
+                    Some(doc) => {
+                        let token = doc
+                            .get_document("resumeToken")
+                            .context("invalid checkpoint")?;
+                        Some(token.to_owned())
+                    }
+                    None => None,
+                }
+            }
+            None => None,
+        };
+
+        let stage = {
+            let mut stage = Document::new();
+            if conditions.is_some() {
+                stage.insert("fullDocument", "updateLookup");
+            }
+            stage
+        };
+        let filter = {
+            let operation_types = ["insert", "update", "replace", "delete"];
+            match conditions {
+                Some(conditions) => doc! {
+                    "$match": {
+                        "$or": [
+                            { "operationType": "delete" },
+                            {
+                                "operationType": {
+                                    "$in": ["insert", "update", "replace"]
+                                },
+                                "$and": [prefix_fields(
+                                    conditions,
+                                    "fullDocument",
+                                )?],
+                            },
+                        ],
+                    }
+                },
+                None => doc! {
+                    "$match": {
+                        "operationType": {
+                            "$in": operation_types.to_vec(),
+                        },
+                    }
+                },
+            }
+        };
+
+        // Without a change to resume after, the stream starts from the
+        // current operation time, so that it can be reopened from there
+        // rather than from whenever it is reopened.
+        let start_at = match &resume_token {
+            Some(_) => None,
+            None => operation_time(&ctx.database()).await?,
+        };
+        let source = WatchSource {
+            collection,
+            stage,
+            filter,
+        };
+        let cursor = source.open(resume_token.as_ref(), start_at).await?;
+        let state = WatchState {
+            source,
+            cursor,
+            key_provider: ctx.key_provider(),
+            checkpoints,
+            checkpoint,
+            resume_token,
+            start_at,
+            pending: None,
+        };
+        let stream = unfold(state, |mut state| async move {
+            let result = state.next::<T>().await.transpose()?;
+            Some((result, state))
+        });
+        Ok(stream)
+    }
+}
+
+/// The change stream to (re)open.
+struct WatchSource {
+    collection: Collection<Document>,
+    stage: Document,
+    filter: Document,
+}
+
+impl WatchSource {
+    /// Opens the change stream after the change with `resume_token`, or else
+    /// at `start_at` (or else from now).
+    async fn open(
+        &self,
+        resume_token: Option<&Document>,
+        start_at: Option<Timestamp>,
+    ) -> DatabaseResult<Cursor<Document>> {
+        let Self {
+            collection,
+            stage,
+            filter,
+        } = self;
+        let pipeline = {
+            let mut stage = stage.to_owned();
+            if let Some(token) = resume_token {
+                stage.insert("resumeAfter", token.to_owned());
+            } else if let Some(start_at) = start_at {
+                stage.insert("startAtOperationTime", start_at);
+            }
+            vec![doc! { "$changeStream": stage }, filter.to_owned()]
+        };
+        trace!(
+            collection = collection.name(),
+            pipeline = %format_pipeline(&pipeline),
+            "watching documents"
+        );
+        collection.aggregate(pipeline, None).await
+    }
+}
+
+struct WatchState {
+    source: WatchSource,
+    cursor: Cursor<Document>,
+    key_provider: Option<Arc<dyn KeyProvider>>,
+    checkpoints: Collection<Document>,
+    checkpoint: Option<String>,
+
+    /// The token of the last change received, to resume after.
+    resume_token: Option<Document>,
+
+    /// The operation time the stream was first opened at, to resume from
+    /// until a change is received.
+    start_at: Option<Timestamp>,
+
+    /// The token of the last change delivered, to be checkpointed.
+    pending: Option<Document>,
+}
+
+impl WatchState {
+    /// Receives the next event, reopening the stream after the last change
+    /// received on transient errors.
+    async fn next_event(&mut self) -> Result<Option<Document>> {
+        let mut attempts = 0;
+        loop {
+            let mut error = match self.cursor.next().await {
+                Some(Ok(event)) => return Ok(Some(event)),
+                Some(Err(error)) => error,
+                None => return Ok(None),
+            };
+            loop {
+                if !is_resumable(&error) || attempts == MAX_RESUME_ATTEMPTS {
+                    return Err(error.into());
+                }
+                attempts += 1;
+                warn!(%error, attempts, "resuming change stream after error");
+                let resume_token = self.resume_token.as_ref();
+                match self.source.open(resume_token, self.start_at).await {
+                    Ok(cursor) => {
+                        self.cursor = cursor;
+                        break;
+                    }
+                    Err(reopen_error) => error = reopen_error,
+                }
+            }
+        }
+    }
+
+    async fn next<T: Entity>(&mut self) -> Result<Option<EntityChange<T>>> {
+        if let (Some(name), Some(token)) =
+            (self.checkpoint.as_ref(), self.pending.take())
+        {
+            let update = doc! {
+                "$set": {
+                    "resumeToken": token,
+                    "updatedAt": BsonDateTime::from_chrono(now()),
+                },
+            };
+            let options = UpdateOptions::builder().upsert(true).build();
+            self.checkpoints
+                .update_one(doc! { "_id": name }, update, options)
+                .await
+                .context("failed to save checkpoint")?;
+        }
+
+        let mut event = match self.next_event().await? {
+            Some(event) => event,
+            None => return Ok(None),
+        };
+        let Self {
+            key_provider,
+            resume_token,
+            pending,
+            ..
+        } = self;
+
+        let token = event
+            .get_document("_id")
+            .context("missing resume token")?
+            .to_owned();
+        *resume_token = Some(token.clone());
+        *pending = Some(token);
+
+        let operation_type = event
+            .get_str("operationType")
+            .context("missing operation type")?
+            .to_owned();
+        let id = || -> Result<EntityId<T>> {
+            let key = event
+                .get_document("documentKey")
+                .context("missing document key")?;
+            let id = key.get("_id").context("missing document id")?;
+            EntityId::from_bson(id).context("invalid document id")
+        };
+        let change = match operation_type.as_str() {
+            "insert" | "replace" => {
+                let doc = match event.remove("fullDocument") {
+                    Some(Bson::Document(doc)) => doc,
+                    _ => bail!("missing full document"),
+                };
+                let key_provider = key_provider.clone();
+                let entity =
+                    with_key_provider(key_provider, || T::from_document(doc))
+                        .context("failed to deserialize entity")?;
+                if operation_type == "insert" {
+                    EntityChange::Inserted(entity)
+                } else {
+                    EntityChange::Replaced(entity)
+                }
+            }
+            "update" => {
+                let id = id()?;
+                let description = event
+                    .get_document("updateDescription")
+                    .context("missing update description")?;
+                let fields = description
+                    .get_document("updatedFields")
+                    .context("missing updated fields")?
+                    .to_owned();
+                let removed_fields = description
+                    .get_array("removedFields")
+                    .context("missing removed fields")?
+                    .iter()
+                    .filter_map(Bson::as_str)
+                    .map(ToOwned::to_owned)
+                    .collect();
+                EntityChange::Updated {
+                    id,
+                    fields,
+                    removed_fields,
+                }
+            }
+            "delete" => EntityChange::Deleted(id()?),
+            operation_type => {
+                trace!(operation_type, "change stream invalidated");
+                return Ok(None);
+            }
+        };
+        Ok(Some(change))
+    }
+}
+
+/// The current operation time of the cluster, as reported by the server
+/// (which standalone servers, that don't support change streams, don't).
+async fn operation_time(database: &Database) -> Result<Option<Timestamp>> {
+    let reply = database
+        .run_command(doc! { "ping": 1 }, None)
+        .await
+        .context("failed to get operation time")?;
+    let time = reply.get_timestamp("operationTime").ok();
+    Ok(time)
+}
+
+/// Whether a change stream can be resumed after `error`.
+fn is_resumable(error: &DatabaseError) -> bool {
+    if error.contains_label("ResumableChangeStreamError") {
+        return true;
+    }
+    match error.kind.as_ref() {
+        DatabaseErrorKind::Io(_)
+        | DatabaseErrorKind::ConnectionPoolCleared { .. }
+        | DatabaseErrorKind::ServerSelection { .. } => true,
+        DatabaseErrorKind::Command(error) => {
+            RESUMABLE_ERROR_CODES.contains(&error.code)
+        }
+        _ => false,
+    }
+}
+
+/// Rewrites the field paths in `conditions` to be relative to `prefix`,
+/// descending into logical operators and `$expr` expressions.
+fn prefix_fields(conditions: Document, prefix: &str) -> Result<Document> {
+    conditions
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match key.as_str() {
+                "$where" | "$jsonSchema" | "$text" => {
+                    bail!("{} is not supported in watch conditions", key)
+                }
+                "$expr" => prefix_expression(value, prefix),
+                key if key.starts_with('$') => match value {
+                    Bson::Array(array) => {
+                        let array = array
+                            .into_iter()
+                            .map(|entry| match entry {
+                                Bson::Document(doc) => {
+                                    prefix_fields(doc, prefix).map(Bson::from)
+                                }
+                                entry => Ok(entry),
+                            })
+                            .collect::<Result<_>>()?;
+                        Bson::Array(array)
+                    }
+                    Bson::Document(doc) => prefix_fields(doc, prefix)?.into(),
+                    value => value,
+                },
+                _ => return Ok((format!("{}.{}", prefix, key), value)),
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// Rewrites the field paths (i.e. "$field") in the aggregation expression
+/// `expression` to be relative to `prefix`.
+fn prefix_expression(expression: Bson, prefix: &str) -> Bson {
+    match expression {
+        Bson::String(path) => {
+            let path = if let Some(variable) = path.strip_prefix("$$") {
+                // $$ROOT and $$CURRENT refer to the change event, rather than
+                // the document; other variables are left alone.
+                let (name, rest) = match variable.split_once('.') {
+                    Some((name, rest)) => (name, Some(rest)),
+                    None => (variable, None),
+                };
+                match (name, rest) {
+                    ("ROOT" | "CURRENT", Some(rest)) => {
+                        format!("$${}.{}.{}", name, prefix, rest)
+                    }
+                    ("ROOT" | "CURRENT", None) => {
+                        format!("$${}.{}", name, prefix)
+                    }
+                    _ => path,
+                }
+            } else if let Some(path) = path.strip_prefix('$') {
+                format!("${}.{}", prefix, path)
+            } else {
+                path
+            };
+            Bson::String(path)
+        }
+        Bson::Array(array) => {
+            let array = array
+                .into_iter()
+                .map(|entry| prefix_expression(entry, prefix))
+                .collect();
+            Bson::Array(array)
+        }
+        Bson::Document(doc) => {
+            let doc = doc
+                .into_iter()
+                .map(|(key, value)| {
+                    // Literals are never field paths.
+                    let value = if key == "$literal" {
+                        value
+                    } else {
+                        prefix_expression(value, prefix)
+                    };
+                    (key, value)
+                })
+                .collect::<Document>();
+            Bson::Document(doc)
+        }
+        expression => expression,
+    }
+}